@@ -0,0 +1,8 @@
+//! A small register VM: parse assembly with [`parse_instructions`] and run it on a [`Vm`].
+
+pub mod vm;
+
+pub use vm::error::{ErrorKind, VmError};
+pub use vm::parser::{parse_instructions, parse_program, Constant, Instruction, Program, Register};
+pub use vm::testing;
+pub use vm::{ExitStatus, HookPoint, StepOutcome, TrapAction, Vm};
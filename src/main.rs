@@ -1,25 +1,429 @@
-use std::fs::read_to_string;
+use std::{
+    fs::read_to_string,
+    io::Write,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
-use vm::parser::parse_instructions;
-mod vm;
+use simple_vm::vm::analysis::cfg::Cfg;
+use simple_vm::vm::analysis::lint::{lint, Severity};
+use simple_vm::vm::analysis::liveness::liveness;
+use simple_vm::vm::analysis::termination::{check_termination, Termination};
+use simple_vm::vm::capabilities::{Capabilities, Capability};
+use simple_vm::vm::coverage;
+use simple_vm::vm::debugger::Debugger;
+use simple_vm::vm::device::{Clock, Console, Device, Random, Timer};
+use simple_vm::vm::expr::compile_expr;
+use simple_vm::vm::lockstep::{lockstep, Lockstep};
+use simple_vm::vm::timing::{timing, Latencies};
+use simple_vm::vm::trace::{ChromeTracer, Granularity, JsonlTracer};
+use simple_vm::{
+    parse_instructions, parse_program, Constant, ExitStatus, Instruction, Program, Vm, VmError,
+};
+
+fn read_program(file_name: &str) -> Vec<Instruction> {
+    let content = read_to_string(file_name).expect("Failed to read a file");
+
+    let parts = content.lines().map(|ch| ch.trim()).collect::<Vec<&str>>();
+    parse_instructions(parts).unwrap()
+}
+
+fn flag_value<'a>(flags: &'a [String], name: &str) -> Option<&'a str> {
+    flags
+        .iter()
+        .position(|flag| flag == name)
+        .map(|i| match flags.get(i + 1) {
+            Some(value) => value.as_str(),
+            None => panic!("{name} expects a value"),
+        })
+}
+
+/// Parses `--clock`: `real`, `fixed:<ms>` or `seq:<ms>,<ms>,...`.
+fn parse_clock(clock: &str) -> Clock {
+    let millis = |ms: &str| {
+        ms.parse()
+            .unwrap_or_else(|_| panic!("--clock expects milliseconds, found {ms}"))
+    };
+    match clock.split_once(':') {
+        None if clock == "real" => Clock::Real,
+        Some(("fixed", ms)) => Clock::Fixed(millis(ms)),
+        Some(("seq", readings)) => Clock::Sequence(readings.split(',').map(millis).collect()),
+        _ => panic!("Unknown clock {clock}, expected real, fixed:<ms> or seq:<ms>,<ms>,..."),
+    }
+}
+
+/// A VM with the console on port 0, the timer on port 1 and the random generator on port 2.
+/// None of them is allowed unless `--allow` lists the capabilities to grant.
+/// Without `--seed` the generator is seeded from the time and the seed goes to stderr
+/// once the program draws a number, so that the run can be repeated.
+fn new_vm(flags: &[String]) -> Vm {
+    let clock = flag_value(flags, "--clock").map_or(Clock::Real, parse_clock);
+    // devices are only granted with --allow
+    let capabilities = flag_value(flags, "--allow").map_or_else(Capabilities::default, |list| {
+        list.parse().unwrap_or_else(|err| panic!("--allow: {err}"))
+    });
+    let builder = Vm::builder()
+        .capabilities(capabilities)
+        .device(Constant::of(0), Box::new(Console::new()))
+        .device(Constant::of(1), Box::new(Timer::with_clock(clock)));
+    match flag_value(flags, "--seed") {
+        Some(seed) => builder.seed(seed.parse().expect("--seed expects a number")),
+        None => builder.device(
+            Constant::of(2),
+            Box::new(ReportSeed {
+                random: Random::new(),
+                reported: false,
+            }),
+        ),
+    }
+    .build()
+}
+
+/// Random generator that prints its seed to stderr on the first draw.
+struct ReportSeed {
+    random: Random,
+    reported: bool,
+}
+
+impl Device for ReportSeed {
+    fn read(&mut self) -> Constant {
+        if !self.reported {
+            self.reported = true;
+            eprintln!("random seed: {}", self.random.seed());
+        }
+        self.random.read()
+    }
+
+    fn write(&mut self, value: Constant) -> Result<(), String> {
+        // a seeded sequence can be repeated without printing anything
+        self.reported = true;
+        self.random.write(value)
+    }
+
+    fn capability(&self) -> Option<Capability> {
+        self.random.capability()
+    }
+}
+
+/// Modification time of `file_name`, `None` if it can't be read.
+fn modified(file_name: &str) -> Option<SystemTime> {
+    std::fs::metadata(file_name)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Runs the VM on a worker thread, printing its state to stderr every `status_every`,
+/// stopping it once `time_limit` has passed and swapping in the program again whenever
+/// the `reload` file changes.
+fn run_controlled(
+    vm: Vm,
+    program: &Program,
+    status_every: Option<Duration>,
+    time_limit: Option<Duration>,
+    reload: Option<&str>,
+) -> (Vm, Result<ExitStatus, VmError>) {
+    let started = Instant::now();
+    let (handle, worker) = vm.spawn(program.clone());
+    let mut next_status = status_every.map(|every| started + every);
+    let mut last_modified = reload.and_then(modified);
+    while !handle.is_finished() {
+        thread::sleep(Duration::from_millis(10));
+        if let Some(file_name) = reload {
+            let modified = modified(file_name);
+            if modified != last_modified {
+                last_modified = modified;
+                let content = read_to_string(file_name).unwrap_or_default();
+                match parse_program(content.lines().map(str::trim).collect()) {
+                    Ok(reloaded) => handle.reload(reloaded.with_file(file_name)),
+                    Err(err) => eprintln!("{file_name}: not reloaded, {err:?}"),
+                }
+            }
+            match handle.reloaded() {
+                Some(Ok(())) => eprintln!("reloaded {file_name}"),
+                Some(Err(err)) => eprintln!("{file_name}: not reloaded, {err}"),
+                None => {}
+            }
+        }
+        if time_limit.is_some_and(|limit| started.elapsed() >= limit) {
+            handle.stop();
+            if let Some(state) = handle.inspect() {
+                eprintln!("time limit reached, stopped at {state}");
+            }
+            break;
+        }
+        if let (Some(every), Some(at)) = (status_every, next_status.as_mut()) {
+            if Instant::now() >= *at {
+                // Paused so that the status line doesn't interleave with program output.
+                handle.pause();
+                if let Some(state) = handle.inspect() {
+                    eprintln!("{state}");
+                }
+                handle.resume();
+                *at += every;
+            }
+        }
+    }
+    worker
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
 
 fn main() {
     let args = std::env::args();
     let input = args.collect::<Vec<String>>();
-    let file_name = match &input[..] {
-        [_, file_name, ..] => file_name,
-        _ => panic!("Usage: call it with file name"),
+    let (file_name, flags) = match &input[..] {
+        [_, command, expr] if command == "compile-expr" => {
+            match compile_expr(expr) {
+                Ok(instructions) => instructions
+                    .iter()
+                    .for_each(|instruction| println!("{instruction}")),
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        [_, command, file_name] if command == "compile" => {
+            let src = read_to_string(file_name).expect("Failed to read a file");
+            match simple_vm::vm::frontend::compile(&src) {
+                Ok(instructions) => instructions
+                    .iter()
+                    .for_each(|instruction| println!("{instruction}")),
+                Err(err) => eprintln!("{file_name}:{err}"),
+            }
+            return;
+        }
+        [_, command, file_name, flags @ ..] if command == "cfg" => {
+            let instructions = read_program(file_name);
+            let cfg = Cfg::build(&instructions);
+            if flags.iter().any(|flag| flag == "--dot") {
+                print!("{}", cfg.to_dot(&instructions));
+            } else {
+                for (i, block) in cfg.blocks.iter().enumerate() {
+                    println!(
+                        "block {i}: lines {}-{} -> {:?}",
+                        block.start + 1,
+                        block.end,
+                        block.successors
+                    );
+                }
+            }
+            return;
+        }
+        [_, command, file_name] if command == "lint" => {
+            let diagnostics = lint(&read_program(file_name));
+            for diagnostic in &diagnostics {
+                println!("{file_name}: {diagnostic}");
+            }
+            if diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.severity == Severity::Error)
+            {
+                std::process::exit(1);
+            }
+            return;
+        }
+        [_, command, file_name] if command == "liveness" => {
+            let instructions = read_program(file_name);
+            let liveness = liveness(&instructions);
+            for (reg, ranges) in liveness.live_ranges() {
+                let lines = ranges
+                    .iter()
+                    .map(|range| format!("{}-{}", range.start + 1, range.end))
+                    .collect::<Vec<_>>();
+                println!("{reg}: live on lines {}", lines.join(", "));
+            }
+            let pressure = liveness.pressure(&Cfg::build(&instructions));
+            for block in &pressure {
+                let registers = block
+                    .registers
+                    .iter()
+                    .map(|reg| reg.to_string())
+                    .collect::<Vec<_>>();
+                println!(
+                    "block {} (lines {}-{}): {} live [{}]",
+                    block.block,
+                    block.pcs.start + 1,
+                    block.pcs.end,
+                    block.max_live,
+                    registers.join(", ")
+                );
+            }
+            let max = pressure.iter().map(|block| block.max_live).max().unwrap_or(0);
+            println!("max simultaneously live registers: {max}");
+            return;
+        }
+        [_, command, file_name, flags @ ..] if command == "check" => {
+            if !flags.iter().any(|flag| flag == "--termination") {
+                panic!("Usage: check <file> --termination [--steps N]");
+            }
+            let max_steps = flag_value(flags, "--steps")
+                .map(|steps| steps.parse().expect("--steps expects a number"))
+                .unwrap_or(100_000);
+            match check_termination(&read_program(file_name), max_steps, 1_000) {
+                Termination::Halts { steps } => println!("halts within {steps} steps"),
+                Termination::Loops { prefix, cycle } => {
+                    let lines = |pcs: &[usize]| pcs.iter().map(|pc| pc + 1).collect::<Vec<_>>();
+                    println!(
+                        "loops forever: after lines {:?} it repeats lines {:?}",
+                        lines(&prefix),
+                        lines(&cycle)
+                    );
+                    std::process::exit(1);
+                }
+                Termination::Unknown(reason) => println!("unknown: {reason}"),
+            }
+            return;
+        }
+        [_, command, file_name, flags @ ..] if command == "debug" => {
+            let script_file = flag_value(flags, "--script")
+                .unwrap_or_else(|| panic!("Usage: debug <file> --script <script file>"));
+            let script = read_to_string(script_file).expect("Failed to read a script");
+            let instructions = read_program(file_name);
+            let mut vm = new_vm(flags);
+            let mut debugger = Debugger::new(&mut vm, &instructions);
+            if let Err(err) = debugger.run_script(&script, &mut std::io::stdout()) {
+                eprintln!("{script_file}: {err}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        [_, command, left, right, flags @ ..] if command == "lockstep" => {
+            let max_steps = flag_value(flags, "--steps")
+                .map(|steps| steps.parse().expect("--steps expects a number"))
+                .unwrap_or(100_000);
+            let (left_instructions, right_instructions) = (read_program(left), read_program(right));
+            let result = lockstep(
+                (&mut new_vm(flags), &left_instructions),
+                (&mut new_vm(flags), &right_instructions),
+                max_steps,
+            );
+            println!("{result}");
+            if let Lockstep::Diverged { .. } | Lockstep::Trapped { .. } = result {
+                std::process::exit(1);
+            }
+            return;
+        }
+        [_, file_name, flags @ ..] => (file_name, flags),
+        _ => panic!(
+            "Usage: call it with file name [--coverage-out <lcov file>] [--jump-history N] [--history N] [--watch <register>] [--events-out <file>] [--allow console,time,random] [--seed <seed>] [--detect-cycles <steps>] [--output <file>] [--reload] [--cycles] [--latencies <opcode>=<cycles>,...] [--status-every <ms>] [--clock real|fixed:<ms>|seq:<ms>,...] [--time-limit <ms>] [--trace-out <file> --trace-format <format>], `compile <source file>`, `compile-expr <expression>`, `cfg <file> [--dot]`, `lint <file>`, `liveness <file>`, `check <file> --termination`, `lockstep <file> <file> [--steps N]` or `debug <file> --script <script file>`"
+        ),
     };
 
-    let content = read_to_string(file_name).expect("Failed to read a file");
+    let program = Program::new(read_program(file_name)).with_file(file_name);
+    let mut vm = new_vm(flags);
+    if let Some(out) = flag_value(flags, "--trace-out") {
+        let file = std::io::BufWriter::new(
+            std::fs::File::create(out).expect("Failed to create a trace file"),
+        );
+        let chrome = |file, granularity| {
+            ChromeTracer::new(file, granularity).expect("Failed to write a trace file")
+        };
+        match flag_value(flags, "--trace-format").unwrap_or("chrome") {
+            "chrome" => vm.set_tracer(Box::new(chrome(file, Granularity::Instruction))),
+            "chrome-blocks" => vm.set_tracer(Box::new(chrome(file, Granularity::Block))),
+            "jsonl" => vm.set_tracer(Box::new(JsonlTracer::new(file))),
+            format => {
+                panic!("Unknown trace format {format}, expected chrome, chrome-blocks or jsonl")
+            }
+        }
+    }
+    if let Some(len) = flag_value(flags, "--jump-history") {
+        vm.set_jump_history(len.parse().expect("--jump-history expects a number"));
+    }
+    if let Some(len) = flag_value(flags, "--history") {
+        vm.set_history(len.parse().expect("--history expects a number"));
+    }
+    for (i, flag) in flags.iter().enumerate() {
+        if flag == "--watch" {
+            let register = flags.get(i + 1).expect("--watch expects a register");
+            let name = register.clone();
+            vm.on_register_change(register, move |old, new, pc| match old {
+                Some(old) => eprintln!("line {}: {name} {old} -> {new}", pc + 1),
+                None => eprintln!("line {}: {name} = {new}", pc + 1),
+            });
+        }
+    }
+    if let Some(out) = flag_value(flags, "--events-out") {
+        let mut file = std::io::BufWriter::new(
+            std::fs::File::create(out).expect("Failed to create an events file"),
+        );
+        vm.on_emit(move |name, value| {
+            writeln!(
+                file,
+                "{{\"name\":\"{}\",\"value\":{value}}}",
+                simple_vm::vm::trace::escape_json(name)
+            )
+            .map_err(|err| format!("events: {err}"))
+        });
+    }
+    if let Some(out) = flag_value(flags, "--output") {
+        vm.set_output(Box::new(std::io::BufWriter::new(
+            std::fs::File::create(out).expect("Failed to create an output file"),
+        )));
+    }
+    if let Some(every) = flag_value(flags, "--detect-cycles") {
+        vm.detect_cycles(every.parse().expect("--detect-cycles expects a step count"));
+    }
+    let coverage_out = flag_value(flags, "--coverage-out");
+    let latencies = flag_value(flags, "--latencies")
+        .map(|table| {
+            table
+                .parse::<Latencies>()
+                .unwrap_or_else(|err| panic!("--latencies: {err}"))
+        })
+        .or_else(|| {
+            flags
+                .iter()
+                .any(|flag| flag == "--cycles")
+                .then(Latencies::default)
+        });
+    if coverage_out.is_some() || latencies.is_some() {
+        vm.enable_coverage();
+    }
+    let millis = |name| {
+        flag_value(flags, name).map(|ms| {
+            Duration::from_millis(
+                ms.parse()
+                    .unwrap_or_else(|_| panic!("{name} expects milliseconds")),
+            )
+        })
+    };
+    let (status_every, time_limit) = (millis("--status-every"), millis("--time-limit"));
+    let reload = flags
+        .iter()
+        .any(|flag| flag == "--reload")
+        .then_some(file_name.as_str());
+    let result = if status_every.is_some() || time_limit.is_some() || reload.is_some() {
+        let result;
+        (vm, result) = run_controlled(vm, &program, status_every, time_limit, reload);
+        result
+    } else {
+        vm.interpret(&program)
+    };
 
-    let parts = content
-        .split('\n')
-        .map(|ch| ch.trim())
-        .collect::<Vec<&str>>();
-    let instructions = parse_instructions(parts).unwrap();
-    let mut vm = vm::Vm::new();
-    vm.interpret(&instructions, 0);
+    if let Err(err) = vm.finish_trace() {
+        eprintln!("Failed to write a trace file: {err}");
+    }
+    if let (Some(out), Some(hits)) = (coverage_out, vm.coverage()) {
+        std::fs::write(out, coverage::to_lcov(file_name, hits)).expect("Failed to write coverage");
+        eprintln!("{}", coverage::summary(hits));
+    }
+    if let (Some(latencies), Some(hits)) = (latencies, vm.coverage()) {
+        eprintln!("{}", timing(&program, hits, &latencies));
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(out) = flag_value(flags, "--metrics-out") {
+        std::fs::write(out, simple_vm::vm::metrics::render()).expect("Failed to write metrics");
+    }
+    if let Err(err) = result {
+        eprintln!("{}", vm.describe(&err));
+        // exit skips destructors, dropping the VM writes out the buffered --output and
+        // --events-out files
+        drop(vm);
+        std::process::exit(1);
+    }
 }
 
 #[test]
@@ -33,7 +437,7 @@ fn super_quick_parse_exec_test() {
         "print a",
     ];
 
-    let instructions = vm::parser::parse_instructions(instructions).unwrap();
-    let mut vm = vm::Vm::new();
-    vm.interpret(&instructions, 0);
+    let instructions = parse_instructions(instructions).unwrap();
+    let mut vm = Vm::new();
+    vm.interpret(&instructions.into()).unwrap();
 }
@@ -1,186 +1,1793 @@
+pub mod analysis;
+pub mod builder;
+pub mod capabilities;
+pub mod coverage;
+mod cycles;
+pub mod debugger;
+pub mod device;
+pub mod error;
+pub mod expr;
+pub mod extension;
+mod fork;
+pub mod frontend;
+pub mod handle;
+pub mod host;
+pub mod input;
+pub mod lockstep;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod parser;
+pub mod state;
+pub mod steps;
+pub mod suspend;
+pub mod testing;
+pub mod timing;
+pub mod trace;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use self::parser::{ConstOrReg, Constant, Instruction, Register};
+use self::builder::VmBuilder;
+use self::capabilities::Capabilities;
+use self::cycles::CycleDetector;
+use self::device::Device;
+use self::error::{ErrorContext, ErrorKind, VmError};
+use self::extension::Extensions;
+use self::host::{HostFn, VmState};
+use self::input::InputSource;
+use self::parser::{
+    BitField, Condition, ConstOrReg, Constant, Flags, Instruction, Program, Register,
+};
+use self::state::State;
+use self::trace::Tracer;
 
 pub struct Vm {
-    registers: HashMap<Register, Constant>,
-    pc: usize,      // program counter
-    max_len: usize, // length of all instructions for interpretation
+    // shared with forks until either side writes, see Vm::fork
+    registers: Arc<HashMap<Register, Constant>>,
+    strings: Arc<HashMap<Register, String>>, // string registers, separate from the integer ones
+    devices: HashMap<Constant, Box<dyn Device>>,
+    host_fns: HashMap<Constant, HostFn>, // called by syscall
+    extensions: Extensions,
+    capabilities: Capabilities,
+    pc: usize,                   // program counter
+    max_len: usize,              // length of all instructions for interpretation
+    program: Arc<[Instruction]>, // the running program, shown around runtime errors
+    coverage: Option<Vec<u64>>,  // hit count per pc, collected once enabled
+    tracer: Option<Box<dyn Tracer>>,
+    jumps: VecDeque<(usize, usize)>, // most recent taken jumps as (from, to) pcs
+    jump_history: usize,
+    history: VecDeque<usize>, // pcs of the most recently executed instructions, oldest first
+    history_len: usize,
+    carry: bool,  // set by add, sub, addc and subb on unsigned overflow or borrow
+    flags: Flags, // set by cmp
+    observers: HashMap<Register, Vec<RegisterObserver>>,
+    events: Option<EventHandler>,
+    hook: Option<Hook>,
+    trap_handler: Option<TrapHandler>,
+    budget: Option<u64>, // instructions left to execute, unlimited when None
+    cancel: Option<Arc<AtomicBool>>,
+    cycles: Option<CycleDetector>,
+    output: Box<dyn Write + Send>, // where print and sprint write, stdout by default
+    input: Box<dyn InputSource>,   // where read takes bytes from, stdin by default
 }
 
+type EventHandler = Box<dyn FnMut(&str, Constant) -> Result<(), String> + Send>;
+
+type Hook = Box<dyn FnMut(HookPoint, &Vm, usize, &Instruction) + Send>;
+
+type TrapHandler = Box<dyn FnMut(&ErrorKind) -> TrapAction + Send>;
+
+type RegisterObserver = Box<dyn FnMut(Option<Constant>, Constant, usize) + Send>;
+
+const DEFAULT_JUMP_HISTORY: usize = 8;
+
+/// When a hook installed with [`Vm::set_hook`] is called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookPoint {
+    /// Before the instruction runs.
+    Before,
+    /// After the instruction has run without error.
+    After,
+}
+
+/// What to do about a fault, decided by a handler installed with [`Vm::on_trap`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapAction {
+    /// Stop the program with the error.
+    Abort,
+    /// Go on with the next instruction as if the failing one wasn't there.
+    Skip,
+    /// Store the value in the register the failing instruction writes, if any, and go on
+    /// with the next instruction.
+    Substitute(Constant),
+}
+
+/// What a single [`Vm::step`] did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// An instruction was executed and the program goes on.
+    Continued,
+    /// The pc is past the last instruction, nothing was executed.
+    Halted,
+    /// The instruction budget set with [`VmBuilder::budget`] is used up, nothing was executed.
+    BudgetExhausted,
+}
+
+/// How a run that didn't fail ended, runtime errors are returned as [`VmError`] instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The pc went past the last instruction.
+    Completed,
+    /// The instruction budget set with [`VmBuilder::budget`] ran out first.
+    BudgetExhausted,
+    /// The host stopped the run through a [`handle::VmHandle`].
+    Stopped,
+    /// The token set with [`Vm::set_cancel_token`] was raised.
+    Cancelled,
+}
+
+/// Instructions shown before and after the failing one in runtime errors.
+const CONTEXT_LINES: usize = 2;
+
 impl Vm {
     pub fn new() -> Self {
         Vm {
-            registers: HashMap::new(),
+            registers: Arc::default(),
+            strings: Arc::default(),
+            devices: HashMap::new(),
+            host_fns: HashMap::new(),
+            extensions: Extensions::default(),
+            capabilities: Capabilities::default(),
             pc: 0,
             max_len: 0,
+            program: Arc::new([]),
+            coverage: None,
+            tracer: None,
+            jumps: VecDeque::new(),
+            jump_history: DEFAULT_JUMP_HISTORY,
+            history: VecDeque::new(),
+            history_len: 0,
+            carry: false,
+            flags: Flags::default(),
+            observers: HashMap::new(),
+            events: None,
+            hook: None,
+            trap_handler: None,
+            budget: None,
+            cancel: None,
+            cycles: None,
+            output: Box::new(std::io::stdout()),
+            input: Box::new(std::io::stdin()),
+        }
+    }
+
+    pub fn builder() -> VmBuilder {
+        VmBuilder::new()
+    }
+
+    /// Makes [`Vm::interpret`] return [`ExitStatus::Cancelled`] before the next instruction
+    /// once `token` is set, e.g. from another thread.
+    pub fn set_cancel_token(&mut self, token: Arc<AtomicBool>) {
+        self.cancel = Some(token);
+    }
+
+    /// Lets the program execute `steps` more instructions, see [`VmBuilder::budget`].
+    pub fn set_budget(&mut self, steps: u64) {
+        self.budget = Some(steps);
+    }
+
+    /// Sets how many of the latest jumps are kept and shown in runtime errors.
+    pub fn set_jump_history(&mut self, len: usize) {
+        self.jump_history = len;
+        while self.jumps.len() > len {
+            self.jumps.pop_front();
         }
     }
 
-    fn mov_const(&mut self, x: &Register, y: Constant) {
-        self.registers.insert(x.clone(), y);
-        self.pc += 1
+    /// Sets how many of the latest executed instructions are kept and attached to runtime
+    /// errors, none by default.
+    pub fn set_history(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Removes the tracer and completes its trace, reporting what dropping it would ignore.
+    pub fn finish_trace(&mut self) -> std::io::Result<()> {
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts counting how many times every instruction is executed.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Vec::new);
+    }
+
+    pub fn coverage(&self) -> Option<&[u64]> {
+        self.coverage.as_deref()
+    }
+
+    /// Value of `register`, `None` while it is uninitialized.
+    pub fn get(&self, register: &Register) -> Option<Constant> {
+        self.registers.get(register).copied()
+    }
+
+    /// Every initialized register with its value, in no particular order.
+    pub fn registers(&self) -> impl Iterator<Item = (&Register, Constant)> {
+        self.registers
+            .iter()
+            .map(|(register, value)| (register, *value))
+    }
+
+    /// Index of the next instruction to execute, past the end once the program has ended.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn attach_device(&mut self, port: Constant, device: Box<dyn Device>) {
+        self.devices.insert(port, device);
+    }
+
+    /// Sends what `print` and `sprint` write to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+        self.output = output;
+    }
+
+    /// Makes `read` take its bytes from `input` instead of stdin.
+    pub fn set_input(&mut self, input: Box<dyn InputSource>) {
+        self.input = input;
     }
 
-    fn mov(&mut self, x: &Register, y: &Register) {
-        match self.registers.get(y) {
-            Some(val_y) => {
-                self.registers.insert(x.clone(), *val_y);
-                self.pc += 1;
+    /// Executes the extension instructions of programs parsed with `extensions`.
+    pub fn set_extensions(&mut self, extensions: Extensions) {
+        self.extensions = extensions;
+    }
+
+    /// Grants the program access to devices, see [`Capabilities`].
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Calls `observer` with the old value, the new value and the pc every time
+    /// `register` is written.
+    pub fn on_register_change(
+        &mut self,
+        register: &str,
+        observer: impl FnMut(Option<Constant>, Constant, usize) + Send + 'static,
+    ) {
+        self.observers
+            .entry(Register::of(register.to_string()))
+            .or_default()
+            .push(Box::new(observer));
+    }
+
+    /// Traps once the VM comes back to an earlier state with no device I/O in between, which
+    /// means it would loop forever. The state is compared every `every` steps.
+    pub fn detect_cycles(&mut self, every: usize) {
+        self.cycles = Some(CycleDetector::new(every));
+    }
+
+    /// Receives the name and value of every `emit`, without a handler events are dropped.
+    /// An error from the handler traps the program with an output error.
+    pub fn on_emit(
+        &mut self,
+        handler: impl FnMut(&str, Constant) -> Result<(), String> + Send + 'static,
+    ) {
+        self.events = Some(Box::new(handler));
+    }
+
+    /// Calls `hook` with the VM, the pc and the instruction before and after every executed
+    /// instruction, replacing any earlier hook.
+    pub fn set_hook(
+        &mut self,
+        hook: impl FnMut(HookPoint, &Vm, usize, &Instruction) + Send + 'static,
+    ) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    /// Lets `handler` decide what happens when an instruction fails, instead of always
+    /// stopping the program. Cycles found by [`Vm::detect_cycles`] always stop it.
+    pub fn on_trap(&mut self, handler: impl FnMut(&ErrorKind) -> TrapAction + Send + 'static) {
+        self.trap_handler = Some(Box::new(handler));
+    }
+
+    fn call_hook(&mut self, point: HookPoint, pc: usize, instruction: &Instruction) {
+        if let Some(mut hook) = self.hook.take() {
+            hook(point, self, pc, instruction);
+            self.hook = Some(hook);
+        }
+    }
+
+    fn set_register(&mut self, x: &Register, value: Constant) {
+        let old = Arc::make_mut(&mut self.registers).insert(x.clone(), value);
+        if let Some(observers) = self.observers.get_mut(x) {
+            for observer in observers {
+                observer(old, value, self.pc);
             }
-            _ => panic!("Register {y} is not initialised"),
         }
     }
 
-    fn add(&mut self, x: &Register, y: &Register) {
-        let line = self.pc + 1;
+    /// 1-based line of the instruction being executed, as reported in errors.
+    fn line(&self) -> usize {
+        self.pc + 1
+    }
+
+    fn mov_const(&mut self, x: &Register, y: Constant) -> Result<(), ErrorKind> {
+        self.set_register(x, y);
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn mov(&mut self, x: &Register, y: &Register) -> Result<(), ErrorKind> {
+        let val_y = self.load(y)?;
+        self.set_register(x, val_y);
+        self.pc += 1;
+        Ok(())
+    }
+
+    /// Values of both operands of a binary instruction, failing if one is uninitialised.
+    fn operands(&self, x: &Register, y: &Register) -> Result<(Constant, Constant), ErrorKind> {
+        let line = self.line();
         match (self.registers.get(x), self.registers.get(y)) {
-            (Some(val_x), Some(val_y)) => {
-                let res: Constant = val_x.wrapping_add(**val_y).into();
-                self.registers.insert(x.clone(), res);
-                self.pc += 1;
-            }
-            (None, Some(_)) => panic!("Register {} must be initialized on line: {}", x, line),
-            (Some(_), None) => panic!("Register {} must be initialized on line: {}", y, line),
-            (None, None) => panic!(
-                "Both registers {} and {} must be initialized on line: {}",
-                x, y, line
-            ),
+            (Some(&val_x), Some(&val_y)) => Ok((val_x, val_y)),
+            (None, Some(_)) => Err(ErrorKind::Uninitialized {
+                register: x.clone(),
+                line,
+            }),
+            (Some(_), None) => Err(ErrorKind::Uninitialized {
+                register: y.clone(),
+                line,
+            }),
+            (None, None) => Err(ErrorKind::BothUninitialized {
+                x: x.clone(),
+                y: y.clone(),
+                line,
+            }),
         }
     }
 
-    fn print(&mut self, x: &Register) {
-        match self.registers.get(x) {
-            Some(val_x) => {
-                if **val_x < 0 {
-                    panic!("Value in register {x} is negative, failed to print it")
-                }
-                let ch = char::from_u32(**val_x as u32)
-                    .expect(format!("Failed to convert value: {val_x} to u32").as_str());
-                print!("{ch}");
-                self.pc += 1;
+    /// Adds or subtracts with carry. A plain `add` or `sub` ignores the incoming carry but
+    /// still sets it, so that `addc` or `subb` can continue a multi-word operation.
+    fn add(
+        &mut self,
+        x: &Register,
+        y: &Register,
+        carry_in: bool,
+        op: fn(Constant, Constant, bool) -> (Constant, bool),
+    ) -> Result<(), ErrorKind> {
+        let (val_x, val_y) = self.operands(x, y)?;
+        let (res, carry) = op(val_x, val_y, carry_in && self.carry);
+        self.carry = carry;
+        self.set_register(x, res);
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn mulh(&mut self, x: &Register, y: &Register) -> Result<(), ErrorKind> {
+        let (val_x, val_y) = self.operands(x, y)?;
+        self.set_register(x, val_x.mul_high(val_y));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn fxmul(&mut self, x: &Register, y: &Register) -> Result<(), ErrorKind> {
+        let (val_x, val_y) = self.operands(x, y)?;
+        self.set_register(x, val_x.fx_mul(val_y));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn mul(&mut self, x: &Register, y: &Register) -> Result<(), ErrorKind> {
+        let (val_x, val_y) = self.operands(x, y)?;
+        self.set_register(x, Constant::of(val_x.wrapping_mul(*val_y)));
+        self.pc += 1;
+        Ok(())
+    }
+
+    /// Divides `x` by `y` with `op`, which returns `None` when dividing by zero.
+    fn divide(
+        &mut self,
+        x: &Register,
+        y: &Register,
+        op: fn(Constant, Constant) -> Option<Constant>,
+    ) -> Result<(), ErrorKind> {
+        let (val_x, val_y) = self.operands(x, y)?;
+        let res = op(val_x, val_y).ok_or(ErrorKind::DivisionByZero { line: self.line() })?;
+        self.set_register(x, res);
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn load(&self, x: &Register) -> Result<Constant, ErrorKind> {
+        self.registers
+            .get(x)
+            .copied()
+            .ok_or_else(|| ErrorKind::Uninitialized {
+                register: x.clone(),
+                line: self.line(),
+            })
+    }
+
+    fn bext(&mut self, x: &Register, y: &Register, field: BitField) -> Result<(), ErrorKind> {
+        let val_y = self.load(y)?;
+        self.set_register(x, val_y.bit_extract(field));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn bins(&mut self, x: &Register, y: &Register, field: BitField) -> Result<(), ErrorKind> {
+        let (val_x, val_y) = self.operands(x, y)?;
+        self.set_register(x, val_x.bit_insert(val_y, field));
+        self.pc += 1;
+        Ok(())
+    }
+
+    /// Rotates `x` left by `amount`, or right when `right` is set.
+    fn rotate(&mut self, x: &Register, amount: &ConstOrReg, right: bool) -> Result<(), ErrorKind> {
+        let val_x = self.load(x)?;
+        let amount = self.get_const_or_load(amount)?;
+        let res = if right {
+            val_x.rotate_right(amount)
+        } else {
+            val_x.rotate_left(amount)
+        };
+        self.set_register(x, res);
+        self.pc += 1;
+        Ok(())
+    }
+
+    /// Stores `op` applied to `x` and `y` in `x`.
+    fn bitwise(
+        &mut self,
+        x: &Register,
+        y: &ConstOrReg,
+        op: fn(Constant, Constant) -> Constant,
+    ) -> Result<(), ErrorKind> {
+        let val_x = self.load(x)?;
+        let val_y = self.get_const_or_load(y)?;
+        self.set_register(x, op(val_x, val_y));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn not(&mut self, x: &Register) -> Result<(), ErrorKind> {
+        let val_x = self.load(x)?;
+        self.set_register(x, !val_x);
+        self.pc += 1;
+        Ok(())
+    }
+
+    /// Stores a count of bits of `y` in `x`.
+    fn count_bits(
+        &mut self,
+        x: &Register,
+        y: &Register,
+        count: fn(i32) -> u32,
+    ) -> Result<(), ErrorKind> {
+        let val_y = self.load(y)?;
+        self.set_register(x, Constant::of(count(*val_y) as i32));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, name: &str, x: &Register) -> Result<(), ErrorKind> {
+        let value = self.load(x)?;
+        let line = self.line();
+        if let Some(handler) = &mut self.events {
+            handler(name, value).map_err(|message| ErrorKind::Output { message, line })?;
+        }
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn syscall(&mut self, n: &Constant) -> Result<(), ErrorKind> {
+        let line = self.line();
+        let mut host_fn = self
+            .host_fns
+            .remove(n)
+            .ok_or(ErrorKind::NoHostFn { n: *n, line })?;
+        let result = host_fn(&mut VmState { vm: self });
+        self.host_fns.insert(*n, host_fn);
+        result.map_err(|message| ErrorKind::HostFn {
+            n: *n,
+            message,
+            line,
+        })?;
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn custom(&mut self, name: &str, operands: &[ConstOrReg]) -> Result<(), ErrorKind> {
+        let line = self.line();
+        let (name, mut extension) =
+            self.extensions
+                .take(name)
+                .ok_or_else(|| ErrorKind::NoExtension {
+                    name: name.to_string(),
+                    line,
+                })?;
+        let result = extension.execute(&mut VmState { vm: self }, operands);
+        self.extensions.put(name.clone(), extension);
+        result.map_err(|message| ErrorKind::Extension {
+            name,
+            message,
+            line,
+        })?;
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn write_output(&mut self, text: &str) -> Result<(), ErrorKind> {
+        let line = self.line();
+        self.output
+            .write_all(text.as_bytes())
+            .map_err(|err| ErrorKind::Output {
+                message: err.to_string(),
+                line,
+            })
+    }
+
+    fn print(&mut self, x: &Register) -> Result<(), ErrorKind> {
+        let val_x = self.load(x)?;
+        let ch =
+            u32::try_from(*val_x)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or(ErrorKind::Unprintable {
+                    value: val_x,
+                    line: self.line(),
+                })?;
+        self.write_output(ch.encode_utf8(&mut [0; 4]))?;
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn read(&mut self, x: &Register) -> Result<(), ErrorKind> {
+        let line = self.line();
+        let byte = self.input.read_byte().map_err(|err| ErrorKind::Input {
+            message: err.to_string(),
+            line,
+        })?;
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+        self.set_register(x, Constant::of(byte.map_or(-1, i32::from)));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn string(&self, x: &Register) -> Result<&str, ErrorKind> {
+        self.strings
+            .get(x)
+            .map(String::as_str)
+            .ok_or_else(|| ErrorKind::UninitializedString {
+                register: x.clone(),
+                line: self.line(),
+            })
+    }
+
+    fn smov(&mut self, x: &Register, text: &str) -> Result<(), ErrorKind> {
+        Arc::make_mut(&mut self.strings).insert(x.clone(), text.to_string());
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn scat(&mut self, x: &Register, y: &Register) -> Result<(), ErrorKind> {
+        let suffix = self.string(y)?.to_string();
+        self.string(x)?;
+        Arc::make_mut(&mut self.strings)
+            .get_mut(x)
+            .unwrap()
+            .push_str(&suffix);
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn slen(&mut self, x: &Register, y: &Register) -> Result<(), ErrorKind> {
+        let len = self.string(y)?.chars().count();
+        let len = i32::try_from(len).map_err(|_| ErrorKind::StringTooLong {
+            register: y.clone(),
+            line: self.line(),
+        })?;
+        self.set_register(x, Constant::of(len));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn sprint(&mut self, x: &Register) -> Result<(), ErrorKind> {
+        let text = self.string(x)?.to_string();
+        self.write_output(&text)?;
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn device(&mut self, port: &Constant) -> Result<&mut dyn Device, ErrorKind> {
+        let line = self.line();
+        let Some(device) = self.devices.get_mut(port) else {
+            return Err(ErrorKind::NoDevice { port: *port, line });
+        };
+        if let Some(capability) = device.capability() {
+            if !self.capabilities.allows(capability) {
+                return Err(ErrorKind::NotAllowed {
+                    capability,
+                    port: *port,
+                    line,
+                });
             }
-            None => (),
         }
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+        Ok(device.as_mut())
+    }
+
+    fn input(&mut self, x: &Register, port: &Constant) -> Result<(), ErrorKind> {
+        let value = self.device(port)?.read();
+        self.set_register(x, value);
+        self.pc += 1;
+        Ok(())
     }
 
-    fn get_const_or_load(&self, x: &ConstOrReg) -> Constant {
+    fn output(&mut self, port: &Constant, x: &Register) -> Result<(), ErrorKind> {
+        let value = self.load(x)?;
+        let line = self.line();
+        self.device(port)?
+            .write(value)
+            .map_err(|message| ErrorKind::Output { message, line })?;
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn poll(&mut self, x: &Register, port: &Constant) -> Result<(), ErrorKind> {
+        let ready = if self.device(port)?.poll() { 1 } else { 0 };
+        self.set_register(x, Constant::of(ready));
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn get_const_or_load(&self, x: &ConstOrReg) -> Result<Constant, ErrorKind> {
         match x {
-            ConstOrReg::Const(constant) => *constant,
-            ConstOrReg::Reg(register) => *self
-                .registers
-                .get(&register)
-                .expect(format!("Rregister {register} must be initialized").as_str()),
+            ConstOrReg::Const(constant) => Ok(*constant),
+            ConstOrReg::Reg(register) => self.load(register),
         }
     }
 
-    fn jumpz(&mut self, x: &ConstOrReg, y: &ConstOrReg) {
-        let value = self.get_const_or_load(x);
+    fn jumpz(&mut self, x: &ConstOrReg, y: &ConstOrReg) -> Result<(), ErrorKind> {
+        let value = self.get_const_or_load(x)?;
         if value == Constant::ZERO {
             self.pc += 1;
-            return;
+            return Ok(());
         }
-        let jump = self.get_const_or_load(y);
+        self.jump(y)
+    }
 
-        let new_pc = if jump < Constant::ZERO {
-            self.pc.checked_sub(jump.abs() as usize)
-        } else {
-            self.pc.checked_add(jump.abs() as usize)
+    fn jz(&mut self, x: &ConstOrReg, y: &ConstOrReg) -> Result<(), ErrorKind> {
+        let value = self.get_const_or_load(x)?;
+        if value != Constant::ZERO {
+            self.pc += 1;
+            return Ok(());
         }
-        .expect(format!("Could not jump {}", jump).as_str());
-        if new_pc > self.max_len {
-            panic!("Trying to jump too far");
+        self.jump(y)
+    }
+
+    fn cmp(&mut self, x: &Register, y: &ConstOrReg) -> Result<(), ErrorKind> {
+        let val_x = self.load(x)?;
+        let val_y = self.get_const_or_load(y)?;
+        self.flags = Flags::compare(val_x, val_y);
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn jump_if(&mut self, condition: Condition, offset: &ConstOrReg) -> Result<(), ErrorKind> {
+        if !condition.holds(self.flags) {
+            self.pc += 1;
+            return Ok(());
+        }
+        self.jump(offset)
+    }
+
+    /// Moves the pc by `offset`, recording the jump.
+    fn jump(&mut self, offset: &ConstOrReg) -> Result<(), ErrorKind> {
+        let jump = self.get_const_or_load(offset)?;
+
+        let new_pc = self
+            .pc
+            .checked_add_signed(*jump as isize)
+            .filter(|new_pc| *new_pc <= self.max_len)
+            .ok_or(ErrorKind::JumpOutOfRange {
+                offset: jump,
+                line: self.line(),
+            })?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(from = self.pc, to = new_pc, "jump");
+        if self.jump_history > 0 {
+            if self.jumps.len() == self.jump_history {
+                self.jumps.pop_front();
+            }
+            self.jumps.push_back((self.pc, new_pc));
         }
         self.pc = new_pc;
+        Ok(())
     }
 
-    pub fn interpret(&mut self, instructions: &[Instruction], start_pc: usize) {
-        self.pc = start_pc;
-        self.max_len = instructions.len();
+    /// The failing instruction and up to `CONTEXT_LINES` before and after it, one per line,
+    /// with the failing one marked.
+    fn context(&self) -> String {
+        let first = self.pc.saturating_sub(CONTEXT_LINES);
+        let last = (self.pc + CONTEXT_LINES).min(self.program.len().saturating_sub(1));
+        let width = (last + 1).to_string().len();
+        (first..=last)
+            .filter_map(|pc| {
+                let marker = if pc == self.pc { "->" } else { "  " };
+                let instruction = self.program.get(pc)?;
+                Some(format!("\n{marker} {:>width$} | {instruction}", pc + 1))
+            })
+            .collect()
+    }
+
+    /// Reports `err` returned by this VM together with the instructions around the failing
+    /// one, the latest executed instructions and the latest jumps that led there.
+    pub fn describe(&self, err: &VmError) -> String {
+        let mut report = err.to_string() + &self.context();
+        report += &format!("\nstate: {}", err.context.state);
+        if !err.context.history.is_empty() {
+            report += "\nlatest executed instructions:";
+            for (pc, instruction) in &err.context.history {
+                report += &format!("\n   {} | {instruction}", pc + 1);
+            }
+        }
+        if !self.jumps.is_empty() {
+            let jumps = self
+                .jumps
+                .iter()
+                .map(|(from, to)| format!("{} → {}", from + 1, to + 1))
+                .collect::<Vec<_>>();
+            report += &format!(
+                "\ncontrol reached here via jumps (line → line): {}",
+                jumps.join(", ")
+            );
+        }
+        report
+    }
+
+    /// Records a runtime error that stops `instructions` at `instruction`.
+    fn trap(
+        &self,
+        kind: ErrorKind,
+        instruction: &Instruction,
+        instructions: &[Instruction],
+    ) -> VmError {
+        #[cfg(feature = "metrics")]
+        metrics::trap();
+        #[cfg(feature = "tracing")]
+        tracing::error!(pc = self.pc, error = %kind, "trap");
+        VmError {
+            kind,
+            context: Box::new(ErrorContext {
+                pc: self.pc,
+                instruction: instruction.clone(),
+                state: State::of(self, instructions),
+                history: self
+                    .history
+                    .iter()
+                    .filter_map(|pc| Some((*pc, instructions.get(*pc)?.clone())))
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Runs `program` from its entry point until it ends, fails or runs out of budget. After
+    /// an error the VM stays on the failing instruction.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(entry = program.entry, instructions = program.len())
+        )
+    )]
+    pub fn interpret(&mut self, program: &Program) -> Result<ExitStatus, VmError> {
+        self.start_program(program, program.entry);
+        self.run(program)
+    }
+
+    /// Steps through `program`, which must have been passed to [`Vm::start`], until it ends,
+    /// fails or runs out of budget.
+    fn run(&mut self, program: &Program) -> Result<ExitStatus, VmError> {
+        #[cfg(feature = "metrics")]
+        let _running = metrics::Running::start();
         loop {
-            if let Some(instruction) = instructions.get(self.pc) {
-                match instruction {
-                    Instruction::Add(x, y) => self.add(&x, &y),
-                    Instruction::Mov(x, y) => match y {
-                        ConstOrReg::Const(constant) => self.mov_const(x, *constant),
-                        ConstOrReg::Reg(reg) => self.mov(x, reg),
-                    },
-                    Instruction::Print(x) => self.print(&x),
-                    Instruction::Jnz(x, y) => self.jumpz(&x, &y),
+            if let Some(cancel) = &self.cancel {
+                if cancel.load(Ordering::Relaxed) {
+                    return Ok(ExitStatus::Cancelled);
+                }
+            }
+            match self.step(program)? {
+                StepOutcome::Continued => {}
+                StepOutcome::Halted => return Ok(ExitStatus::Completed),
+                StepOutcome::BudgetExhausted => return Ok(ExitStatus::BudgetExhausted),
+            }
+        }
+    }
+
+    /// Prepares the VM to run `instructions` from `start_pc` one [`Vm::step`] at a time.
+    pub fn start(&mut self, instructions: &[Instruction], start_pc: usize) {
+        self.load_program(instructions.into(), start_pc);
+    }
+
+    /// Like [`Vm::start`], sharing the instructions of `program` instead of copying them.
+    pub(crate) fn start_program(&mut self, program: &Program, start_pc: usize) {
+        self.load_program(program.instructions.clone(), start_pc);
+    }
+
+    fn load_program(&mut self, program: Arc<[Instruction]>, start_pc: usize) {
+        self.pc = start_pc;
+        self.max_len = program.len();
+        if let Some(hits) = &mut self.coverage {
+            hits.resize(program.len(), 0);
+        }
+        self.program = program;
+        self.jumps.clear();
+        self.history.clear();
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+    }
+
+    /// Executes the instruction at the current pc of `instructions`, normally the program
+    /// passed to [`Vm::start`]. After an error the VM stays on the failing instruction.
+    pub fn step(&mut self, instructions: &[Instruction]) -> Result<StepOutcome, VmError> {
+        let Some(instruction) = instructions.get(self.pc) else {
+            return Ok(StepOutcome::Halted);
+        };
+        match &mut self.budget {
+            Some(0) => return Ok(StepOutcome::BudgetExhausted),
+            Some(left) => *left -= 1,
+            None => {}
+        }
+        if let Some(hits) = &mut self.coverage {
+            if hits.len() < instructions.len() {
+                hits.resize(instructions.len(), 0);
+            }
+            hits[self.pc] += 1;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::trace!(pc = self.pc, %instruction, "execute");
+        #[cfg(feature = "metrics")]
+        metrics::instruction_executed();
+        let pc = self.pc;
+        if let Some(tracer) = &mut self.tracer {
+            if let Err(err) = tracer.before(pc, instruction) {
+                let kind = ErrorKind::Output {
+                    message: format!("trace: {err}"),
+                    line: pc + 1,
+                };
+                return Err(self.trap(kind, instruction, instructions));
+            }
+        }
+        self.call_hook(HookPoint::Before, pc, instruction);
+        let result = match instruction {
+            Instruction::Add(x, y) => self.add(x, y, false, Constant::add_with_carry),
+            Instruction::Sub(x, y) => self.add(x, y, false, Constant::sub_with_borrow),
+            Instruction::Mul(x, y) => self.mul(x, y),
+            Instruction::Div(x, y) => self.divide(x, y, Constant::quotient),
+            Instruction::Mod(x, y) => self.divide(x, y, Constant::remainder),
+            Instruction::AddC(x, y) => self.add(x, y, true, Constant::add_with_carry),
+            Instruction::SubB(x, y) => self.add(x, y, true, Constant::sub_with_borrow),
+            Instruction::MulH(x, y) => self.mulh(x, y),
+            Instruction::Emit(name, x) => self.emit(name, x),
+            Instruction::Mov(x, y) => match y {
+                ConstOrReg::Const(constant) => self.mov_const(x, *constant),
+                ConstOrReg::Reg(reg) => self.mov(x, reg),
+            },
+            Instruction::Print(x) => self.print(x),
+            Instruction::Read(x) => self.read(x),
+            Instruction::Syscall(n) => self.syscall(n),
+            Instruction::Custom(name, operands) => self.custom(name, operands),
+            Instruction::Jnz(x, y) => self.jumpz(x, y),
+            Instruction::Jz(x, y) => self.jz(x, y),
+            Instruction::Jmp(offset) => self.jump(offset),
+            Instruction::Cmp(x, y) => self.cmp(x, y),
+            Instruction::JumpIf(condition, offset) => self.jump_if(*condition, offset),
+            Instruction::In(x, port) => self.input(x, port),
+            Instruction::Out(port, x) => self.output(port, x),
+            Instruction::Poll(x, port) => self.poll(x, port),
+            Instruction::SMov(x, text) => self.smov(x, text),
+            Instruction::SCat(x, y) => self.scat(x, y),
+            Instruction::SLen(x, y) => self.slen(x, y),
+            Instruction::SPrint(x) => self.sprint(x),
+            Instruction::FxMul(x, y) => self.fxmul(x, y),
+            Instruction::FxDiv(x, y) => self.divide(x, y, Constant::fx_div),
+            Instruction::BExt(x, y, field) => self.bext(x, y, *field),
+            Instruction::BIns(x, y, field) => self.bins(x, y, *field),
+            Instruction::And(x, y) => self.bitwise(x, y, |a, b| a & b),
+            Instruction::Or(x, y) => self.bitwise(x, y, |a, b| a | b),
+            Instruction::Xor(x, y) => self.bitwise(x, y, |a, b| a ^ b),
+            Instruction::Not(x) => self.not(x),
+            Instruction::Shl(x, n) => self.bitwise(x, n, Constant::shift_left),
+            Instruction::Shr(x, n) => self.bitwise(x, n, Constant::shift_right),
+            Instruction::Rol(x, n) => self.rotate(x, n, false),
+            Instruction::Ror(x, n) => self.rotate(x, n, true),
+            Instruction::Popcnt(x, y) => self.count_bits(x, y, i32::count_ones),
+            Instruction::Clz(x, y) => self.count_bits(x, y, i32::leading_zeros),
+            Instruction::Ctz(x, y) => self.count_bits(x, y, i32::trailing_zeros),
+        };
+        if let Err(kind) = result {
+            let action = self
+                .trap_handler
+                .as_mut()
+                .map_or(TrapAction::Abort, |handler| handler(&kind));
+            match action {
+                TrapAction::Abort => return Err(self.trap(kind, instruction, instructions)),
+                TrapAction::Skip => {}
+                TrapAction::Substitute(value) => {
+                    if let Some(x) = analysis::writes(instruction) {
+                        self.set_register(x, value);
+                    }
                 }
-            } else {
-                return;
             }
+            self.pc += 1;
+        }
+        if let Some(tracer) = &mut self.tracer {
+            if let Err(err) = tracer.after(pc, instruction, &self.registers) {
+                let kind = ErrorKind::Output {
+                    message: format!("trace: {err}"),
+                    line: pc + 1,
+                };
+                return Err(self.trap(kind, instruction, instructions));
+            }
+        }
+        self.call_hook(HookPoint::After, pc, instruction);
+        if self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(pc);
         }
+        if let Some(mut cycles) = self.cycles.take() {
+            let repeated = cycles.step(|| State::of(self, instructions));
+            self.cycles = Some(cycles);
+            if let Some((state, steps)) = repeated {
+                let kind = ErrorKind::Cycle {
+                    state: Box::new(state),
+                    steps,
+                    line: self.line(),
+                };
+                let at = instructions.get(self.pc).unwrap_or(instruction);
+                return Err(self.trap(kind, at, instructions));
+            }
+        }
+        Ok(StepOutcome::Continued)
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Vm;
-    use crate::vm::parser::{parse_instructions, Constant, Register};
+    use super::{Capabilities, ErrorKind, ExitStatus, HookPoint, StepOutcome, TrapAction, Vm};
+    use crate::vm::device::{Clock, Console, Device, Random, Timer};
+    use crate::vm::parser::{parse_instructions, parse_program, Constant, Flags, Register};
+    use crate::vm::testing::assert_program;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
 
     #[test]
     fn test_mov() {
-        let instructions = parse_instructions(vec!["mov a 1", "mov b a"]).unwrap();
+        let instructions = parse_program(vec!["mov a 1", "mov b a"]).unwrap();
         let a = Register::of("a".to_string());
         let b = Register::of("b".to_string());
 
         let mut vm = Vm::new();
-        vm.interpret(&instructions, 0);
+        vm.interpret(&instructions).unwrap();
         assert_eq!(vm.pc, 2);
         assert_eq!(*vm.registers.get(&a).unwrap(), Constant::of(1));
         assert_eq!(*vm.registers.get(&b).unwrap(), Constant::of(1));
     }
 
+    #[test]
+    fn test_entry_point() {
+        let program = parse_program(vec!["mov a 1", "mov b 2"])
+            .unwrap()
+            .with_entry(1);
+        let mut vm = Vm::new();
+        vm.interpret(&program).unwrap();
+        assert_eq!(vm.get(&Register::of("a".to_string())), None);
+        assert_eq!(
+            vm.get(&Register::of("b".to_string())),
+            Some(Constant::of(2))
+        );
+    }
+
     #[test]
     fn test_add() {
-        let instructions = parse_instructions(vec!["mov a 1", "mov b a", "add a b"]).unwrap();
+        let instructions = parse_program(vec!["mov a 1", "mov b a", "add a b"]).unwrap();
         let a = Register::of("a".to_string());
         let b = Register::of("b".to_string());
 
         let mut vm = Vm::new();
-        vm.interpret(&instructions, 0);
+        vm.interpret(&instructions).unwrap();
         assert_eq!(vm.pc, 3);
         assert_eq!(*vm.registers.get(&a).unwrap(), Constant::of(2));
         assert_eq!(*vm.registers.get(&b).unwrap(), Constant::of(1));
     }
 
-    // TODO add buffer for printing in vm
-    // #[test]
-    // fn check_print() {
-    // }
+    #[test]
+    fn test_arithmetic() {
+        let instructions = parse_program(vec![
+            "mov a 7",
+            "mov b -2",
+            "mov c a",
+            "mov d a",
+            "mov e a",
+            "sub a b",
+            "mul b c",
+            "div c b",
+            "mod d b",
+            "mov big 2147483647",
+            "mul big e",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        let reg = |name: &str| vm.registers[&Register::of(name.to_string())];
+        assert_eq!(
+            [reg("a"), reg("b"), reg("c"), reg("d"), reg("big")],
+            [9, -14, 0, 7, 2147483641].map(Constant::of)
+        );
+        assert!(vm.carry);
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let instructions =
+            parse_program(vec!["mov a 7", "mov b 0", "div a b", "mod a b", "print a"]).unwrap();
+        assert_eq!(
+            Vm::new().interpret(&instructions).map_err(|err| err.kind),
+            Err(ErrorKind::DivisionByZero { line: 3 })
+        );
+        let mut vm = Vm::new();
+        vm.on_trap(|fault| match fault {
+            ErrorKind::DivisionByZero { line: 3 } => TrapAction::Substitute(Constant::of(65)),
+            _ => TrapAction::Skip,
+        });
+        assert_eq!(vm.interpret(&instructions), Ok(ExitStatus::Completed));
+        assert_eq!(
+            vm.get(&Register::of("a".to_string())),
+            Some(Constant::of(65))
+        );
+    }
+
+    #[test]
+    fn test_print() {
+        assert_program!(
+            "mov a 104\nprint a\nmov a 105\nprint a\nsmov s \" there\"\nsprint s",
+            output = "hi there",
+            regs = { a: 105 },
+        );
+    }
 
     #[test]
     fn test_jump() {
         let instructions =
-            parse_instructions(vec!["mov a 1", "mov b a", "jnz b 2", "add a b", "mov c 0"])
-                .unwrap();
+            parse_program(vec!["mov a 1", "mov b a", "jnz b 2", "add a b", "mov c 0"]).unwrap();
         let a = Register::of("a".to_string());
         let b = Register::of("b".to_string());
         let c = Register::of("c".to_string());
 
         let mut vm = Vm::new();
-        vm.interpret(&instructions, 0);
+        vm.interpret(&instructions).unwrap();
         assert_eq!(vm.pc, 5);
         assert_eq!(*vm.registers.get(&a).unwrap(), Constant::of(1));
         assert_eq!(*vm.registers.get(&b).unwrap(), Constant::of(1));
         assert_eq!(*vm.registers.get(&c).unwrap(), Constant::of(0));
     }
 
+    #[test]
+    fn test_jz_and_jmp() {
+        let instructions = parse_program(vec![
+            "mov n 3",
+            "mov sum 0",
+            "mov minus -1",
+            "jz n 4",
+            "add sum n",
+            "add n minus",
+            "jmp -3",
+            "jz sum 2",
+            "mov done 1",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        let reg = |name: &str| vm.get(&Register::of(name.to_string()));
+        assert_eq!(
+            [reg("sum"), reg("done")],
+            [Some(Constant::of(6)), Some(Constant::of(1))]
+        );
+        let err = vm
+            .interpret(&parse_program(vec!["jmp -1"]).unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::JumpOutOfRange {
+                offset: Constant::of(-1),
+                line: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_conditional_jumps() {
+        let instructions = parse_program(vec![
+            "mov i 0",
+            "mov one 1",
+            "mov sum 0",
+            "add sum i",
+            "add i one",
+            "cmp i 5",
+            "jle -3",
+            "cmp sum 15",
+            "jne 2",
+            "mov ok 1",
+            "cmp i 0",
+            "jge 2",
+            "mov bad 1",
+            "jg 2",
+            "mov bad 1",
+            "je 2",
+            "mov ne 1",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        let reg = |name: &str| vm.get(&Register::of(name.to_string()));
+        assert_eq!(
+            [reg("sum"), reg("ok"), reg("ne")],
+            [
+                Some(Constant::of(15)),
+                Some(Constant::of(1)),
+                Some(Constant::of(1))
+            ]
+        );
+        assert_eq!(reg("bad"), None);
+        assert_eq!(vm.flags, Flags::default());
+    }
+
+    #[test]
+    fn test_inspection() {
+        let instructions = parse_program(vec!["mov a 3", "mov b a", "add b a"]).unwrap();
+        let mut vm = Vm::new();
+        assert_eq!(vm.interpret(&instructions), Ok(ExitStatus::Completed));
+        assert_eq!(vm.pc(), 3);
+        assert_eq!(
+            vm.get(&Register::of("b".to_string())),
+            Some(Constant::of(6))
+        );
+        assert_eq!(vm.get(&Register::of("c".to_string())), None);
+        let mut registers = vm
+            .registers()
+            .map(|(register, value)| (register.to_string(), *value))
+            .collect::<Vec<_>>();
+        registers.sort();
+        assert_eq!(registers, [("a".to_string(), 3), ("b".to_string(), 6)]);
+    }
+
     #[test]
     fn test_backward_jump() {
         let instructions =
-            parse_instructions(vec!["mov a 2", "mov b -1", "add a b", "jnz a -1"]).unwrap();
+            parse_program(vec!["mov a 2", "mov b -1", "add a b", "jnz a -1"]).unwrap();
 
         let a = Register::of("a".to_string());
         let b = Register::of("b".to_string());
         let mut vm = Vm::new();
-        vm.interpret(&instructions, 0);
+        vm.interpret(&instructions).unwrap();
         assert_eq!(vm.pc, 4);
         assert_eq!(*vm.registers.get(&a).unwrap(), Constant::of(0));
         assert_eq!(*vm.registers.get(&b).unwrap(), Constant::of(-1));
     }
+
+    #[test]
+    fn test_step() {
+        let instructions = parse_instructions(vec!["mov a 1", "jnz a 2", "mov a 0"]).unwrap();
+        let mut vm = Vm::new();
+        vm.start(&instructions, 0);
+        assert_eq!(vm.step(&instructions), Ok(StepOutcome::Continued));
+        assert_eq!(vm.pc, 1);
+        assert_eq!(vm.step(&instructions), Ok(StepOutcome::Continued));
+        assert_eq!(vm.pc, 3);
+        assert_eq!(vm.step(&instructions), Ok(StepOutcome::Halted));
+        assert_eq!(vm.step(&instructions), Ok(StepOutcome::Halted));
+        assert_eq!(
+            vm.registers[&Register::of("a".to_string())],
+            Constant::of(1)
+        );
+
+        let instructions = parse_instructions(vec!["print b"]).unwrap();
+        vm.start(&instructions, 0);
+        assert!(matches!(
+            vm.step(&instructions).map_err(|err| err.kind),
+            Err(ErrorKind::Uninitialized { line: 1, .. })
+        ));
+        assert_eq!(vm.pc, 0);
+    }
+
+    struct Echo(Constant);
+
+    impl Device for Echo {
+        fn read(&mut self) -> Constant {
+            self.0
+        }
+
+        fn write(&mut self, value: Constant) -> Result<(), String> {
+            self.0 = value;
+            Ok(())
+        }
+
+        fn poll(&mut self) -> bool {
+            self.0 != Constant::ZERO
+        }
+    }
+
+    #[test]
+    fn test_device_io() {
+        let instructions = parse_program(vec!["mov a 7", "out 3 a", "mov a 0", "in b 3"]).unwrap();
+        let b = Register::of("b".to_string());
+
+        let mut vm = Vm::new();
+        vm.attach_device(Constant::of(3), Box::new(Echo(Constant::ZERO)));
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(vm.pc, 4);
+        assert_eq!(*vm.registers.get(&b).unwrap(), Constant::of(7));
+    }
+
+    #[test]
+    fn test_unprintable_console_output() {
+        let instructions = parse_program(vec!["mov a -5", "out 0 a"]).unwrap();
+        let mut vm = Vm::new();
+        vm.set_capabilities(Capabilities::all());
+        vm.attach_device(Constant::of(0), Box::new(Console::new()));
+        let err = vm.interpret(&instructions).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Output {
+                message: "value -5 is not a character".to_string(),
+                line: 2
+            }
+        );
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_poll() {
+        let instructions =
+            parse_program(vec!["poll a 3", "mov b 1", "out 3 b", "poll b 3"]).unwrap();
+        let a = Register::of("a".to_string());
+        let b = Register::of("b".to_string());
+
+        let mut vm = Vm::new();
+        vm.attach_device(Constant::of(3), Box::new(Echo(Constant::ZERO)));
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(*vm.registers.get(&a).unwrap(), Constant::of(0));
+        assert_eq!(*vm.registers.get(&b).unwrap(), Constant::of(1));
+    }
+
+    #[test]
+    fn test_read() {
+        let instructions = parse_program(vec!["read a", "read b", "read c"]).unwrap();
+        let mut vm = Vm::new();
+        vm.set_input(Box::new(&b"hi"[..]));
+        vm.interpret(&instructions).unwrap();
+        let values = ["a", "b", "c"].map(|name| vm.registers[&Register::of(name.to_string())]);
+        assert_eq!(values, [104, 105, -1].map(Constant::of));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let instructions = parse_program(vec!["in a 1", "in b 2"]).unwrap();
+        let mut vm = Vm::new();
+        vm.attach_device(Constant::of(1), Box::new(Timer::new()));
+        vm.attach_device(Constant::of(2), Box::new(Random::new()));
+        vm.set_capabilities("time".parse().unwrap());
+        assert_eq!(
+            vm.interpret(&instructions).unwrap_err().to_string(),
+            "Program is not allowed to use random (device on port 2) on line: 2"
+        );
+    }
+
+    #[test]
+    fn test_error_context() {
+        let mut program = vec!["mov c 0"; 7];
+        program.extend(["mov a 1", "mov b 0", "fxdiv a b", "print a"]);
+        let instructions = parse_program(program).unwrap();
+        let mut vm = Vm::new();
+        let err = vm.interpret(&instructions).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::DivisionByZero { line: 10 });
+        let context = &err.context;
+        assert_eq!(
+            (context.pc, context.instruction.to_string()),
+            (9, "fxdiv a b".to_string())
+        );
+        assert_eq!(context.state.registers[0], ("a".to_string(), 1));
+        assert_eq!(
+            vm.describe(&err),
+            "Division by zero on line: 10\n    8 | mov a 1\n    9 | mov b 0\n-> 10 | fxdiv a b\n   11 | print a\n\
+             state: line 10, a = 1, b = 0, c = 0"
+        );
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        let instructions =
+            parse_program(vec!["mov a 1", "mov b 2", "jnz a 1", "jnz b -1", "print a"]).unwrap();
+        let mut vm = Vm::new();
+        vm.detect_cycles(1);
+        assert_eq!(
+            vm.interpret(&instructions).unwrap_err().to_string(),
+            "Non-terminating cycle detected on line: 4, the VM came back to line 4, a = 1, b = 2 after 2 steps"
+        );
+    }
+
+    #[test]
+    fn test_cycle_detection_with_io() {
+        // Waits for the clock to tick, the state repeats but the device ends the loop.
+        let instructions = parse_program(vec!["in t 1", "jnz t 2", "jnz 1 -2", "mov a 1"]).unwrap();
+        let mut readings = vec![0; 20];
+        readings.push(1);
+        let mut vm = Vm::new();
+        vm.attach_device(
+            Constant::of(1),
+            Box::new(Timer::with_clock(Clock::Sequence(readings))),
+        );
+        vm.set_capabilities("time".parse().unwrap());
+        vm.detect_cycles(1);
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(
+            vm.registers[&Register::of("a".to_string())],
+            Constant::of(1)
+        );
+    }
+
+    #[test]
+    fn test_missing_device() {
+        let instructions = parse_program(vec!["in a 5"]).unwrap();
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.interpret(&instructions).map_err(|err| err.kind),
+            Err(ErrorKind::NoDevice {
+                port: Constant::of(5),
+                line: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        // 1.5 * 2.5 / 0.5
+        let instructions = parse_program(vec![
+            "mov a 98304",
+            "mov b 163840",
+            "mov c 32768",
+            "fxmul a b",
+            "fxdiv a c",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(
+            vm.registers[&Register::of("a".to_string())],
+            Constant::of(491520)
+        );
+    }
+
+    #[test]
+    fn test_fixed_point_division_by_zero() {
+        let instructions = parse_program(vec!["mov a 1", "mov b 0", "fxdiv a b"]).unwrap();
+        assert_eq!(
+            Vm::new().interpret(&instructions).map_err(|err| err.kind),
+            Err(ErrorKind::DivisionByZero { line: 3 })
+        );
+    }
+
+    #[test]
+    fn test_bit_fields() {
+        let instructions = parse_program(vec![
+            "mov a 1193046", // 0x123456
+            "bext b a 8 8",
+            "mov c 15",
+            "bins a c 20 4",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(
+            vm.registers[&Register::of("b".to_string())],
+            Constant::of(0x34)
+        );
+        assert_eq!(
+            vm.registers[&Register::of("a".to_string())],
+            Constant::of(0xf23456)
+        );
+    }
+
+    #[test]
+    fn test_rotate() {
+        let instructions =
+            parse_program(vec!["mov a 6", "mov n 2", "ror a n", "rol a 5", "ror a 33"]).unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(
+            vm.registers[&Register::of("a".to_string())],
+            Constant::of(24)
+        );
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let instructions = parse_program(vec![
+            "mov a 12", "mov b 10", "mov c a", "mov d a", "and a b", "or c b", "xor d 10",
+            "mov e b", "not e", "mov f -1", "mov n 28", "shl b n", "shr f n",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        let reg = |name: &str| vm.registers[&Register::of(name.to_string())];
+        assert_eq!(
+            [reg("a"), reg("c"), reg("d"), reg("e"), reg("b"), reg("f")],
+            [8, 14, 6, -11, -1610612736, 15].map(Constant::of)
+        );
+    }
+
+    #[test]
+    fn test_bit_counts() {
+        let instructions = parse_program(vec![
+            "mov a 40",
+            "popcnt p a",
+            "clz l a",
+            "ctz t a",
+            "mov z 0",
+            "ctz z z",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        let reg = |name: &str| vm.registers[&Register::of(name.to_string())];
+        assert_eq!(
+            [reg("p"), reg("l"), reg("t"), reg("z")],
+            [2, 26, 3, 32].map(Constant::of)
+        );
+    }
+
+    #[test]
+    fn test_multi_word_arithmetic() {
+        // 0x1_ffffffff + 0x0_00000001 - 0x2_00000001
+        let instructions = parse_program(vec![
+            "mov lo -1",
+            "mov hi 1",
+            "mov one 1",
+            "mov zero 0",
+            "add lo one",
+            "addc hi zero",
+            "mov two 2",
+            "subb lo one",
+            "subb hi two",
+            "mov m 65536",
+            "mulh m m",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        let reg = |name: &str| vm.registers[&Register::of(name.to_string())];
+        assert_eq!([reg("lo"), reg("hi")], [Constant::of(-1), Constant::of(-1)]);
+        assert!(vm.carry);
+        assert_eq!(reg("m"), Constant::of(1));
+    }
+
+    #[test]
+    fn test_emit() {
+        let instructions = parse_program(vec![
+            "mov a 2",
+            "emit start a",
+            "mov b -1",
+            "add a b",
+            "emit a a",
+        ])
+        .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut vm = Vm::new();
+        let seen = events.clone();
+        vm.on_emit(move |name, value| {
+            seen.lock().unwrap().push((name.to_string(), *value));
+            Ok(())
+        });
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![("start".to_string(), 2), ("a".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_emit_failure() {
+        let instructions = parse_program(vec!["mov a 2", "emit start a"]).unwrap();
+        let mut vm = Vm::new();
+        vm.on_emit(|_, _| Err("disk full".to_string()));
+        let err = vm.interpret(&instructions).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Output {
+                message: "disk full".to_string(),
+                line: 2
+            }
+        );
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_hook() {
+        let instructions = parse_program(vec!["mov a 1", "print b"]).unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut vm = Vm::new();
+        let seen = calls.clone();
+        vm.set_hook(move |point, vm, pc, instruction| {
+            let a = vm.registers.get(&Register::of("a".to_string())).copied();
+            seen.lock()
+                .unwrap()
+                .push((point, pc, instruction.to_string(), a));
+        });
+        vm.interpret(&instructions).unwrap_err();
+        let a = Some(Constant::of(1));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                (HookPoint::Before, 0, "mov a 1".to_string(), None),
+                (HookPoint::After, 0, "mov a 1".to_string(), a),
+                (HookPoint::Before, 1, "print b".to_string(), a),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let instructions = parse_program(vec![
+            r#"smov s "ab""#,
+            r#"smov t "c""#,
+            "scat s t",
+            "scat s s",
+            "slen n s",
+            "sprint s",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(vm.strings[&Register::of("s".to_string())], "abcabc");
+        assert_eq!(
+            vm.registers[&Register::of("n".to_string())],
+            Constant::of(6)
+        );
+    }
+
+    #[test]
+    fn test_uninitialized_string() {
+        let instructions = parse_program(vec![r#"smov s "a""#, "scat s t"]).unwrap();
+        assert_eq!(
+            Vm::new().interpret(&instructions).unwrap_err().to_string(),
+            "String register t must be initialized on line: 2"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let run = |program: Vec<&str>| {
+            let instructions = parse_program(program).unwrap();
+            Vm::new().interpret(&instructions).unwrap_err().kind
+        };
+        let register = |name: &str| Register::of(name.to_string());
+        assert_eq!(
+            run(vec!["mov a 1", "print b"]),
+            ErrorKind::Uninitialized {
+                register: register("b"),
+                line: 2
+            }
+        );
+        assert_eq!(
+            run(vec!["add a b"]),
+            ErrorKind::BothUninitialized {
+                x: register("a"),
+                y: register("b"),
+                line: 1
+            }
+        );
+        assert_eq!(
+            run(vec!["mov a -1", "print a"]),
+            ErrorKind::Unprintable {
+                value: Constant::of(-1),
+                line: 2
+            }
+        );
+        assert_eq!(
+            run(vec!["mov a 1", "jnz a -2"]),
+            ErrorKind::JumpOutOfRange {
+                offset: Constant::of(-2),
+                line: 2
+            }
+        );
+        assert_eq!(
+            run(vec!["mov a 1", "jnz a 2"]),
+            ErrorKind::JumpOutOfRange {
+                offset: Constant::of(2),
+                line: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_shared_program() {
+        // Computes n * 3 for n in 1..=4 on one thread each.
+        let program = parse_program(vec!["mov a n", "add a n", "add a n"]).unwrap();
+        let workers = (1..=4)
+            .map(|n| {
+                let program = program.clone();
+                std::thread::spawn(move || {
+                    let mut vm = Vm::builder().register("n", n).build();
+                    vm.interpret(&program).unwrap();
+                    assert!(Arc::ptr_eq(&vm.program, &program.instructions));
+                    *vm.get(&Register::of("a".to_string())).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results, [3, 6, 9, 12]);
+    }
+
+    #[test]
+    fn test_cancel_token() {
+        let program = parse_program(vec!["mov a 1", "jnz a 0"]).unwrap();
+        let token = Arc::new(AtomicBool::new(false));
+        let mut vm = Vm::new();
+        vm.set_cancel_token(token.clone());
+        let worker = std::thread::spawn(move || (vm.interpret(&program), vm.pc));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        token.store(true, Ordering::Relaxed);
+        assert_eq!(worker.join().unwrap(), (Ok(ExitStatus::Cancelled), 1));
+    }
+
+    #[test]
+    fn test_trap_handler() {
+        let instructions = parse_program(vec![
+            "mov a 1",
+            "fxdiv a b",
+            "print c",
+            "mov d a",
+            "add d e",
+            "jnz a -9",
+        ])
+        .unwrap();
+        let faults = Arc::new(Mutex::new(Vec::new()));
+        let mut vm = Vm::new();
+        let seen = faults.clone();
+        vm.on_trap(move |fault| {
+            seen.lock().unwrap().push(fault.line());
+            match fault {
+                ErrorKind::Uninitialized { register, .. } if register.to_string() == "b" => {
+                    TrapAction::Substitute(Constant::of(7))
+                }
+                ErrorKind::JumpOutOfRange { .. } => TrapAction::Abort,
+                _ => TrapAction::Skip,
+            }
+        });
+        let err = vm.interpret(&instructions).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::JumpOutOfRange { line: 6, .. }
+        ));
+        assert_eq!(*faults.lock().unwrap(), [2, 3, 5, 6]);
+        assert_eq!(
+            vm.get(&Register::of("a".to_string())),
+            Some(Constant::of(7))
+        );
+        assert_eq!(
+            vm.get(&Register::of("d".to_string())),
+            Some(Constant::of(7))
+        );
+    }
+
+    #[test]
+    fn test_register_observer() {
+        let instructions = parse_program(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "mov b 5",
+        ])
+        .unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut vm = Vm::new();
+        let seen = changes.clone();
+        vm.on_register_change("a", move |old, new, pc| {
+            seen.lock().unwrap().push((old.map(|v| *v), *new, pc))
+        });
+        vm.interpret(&instructions).unwrap();
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![(None, 2, 0), (Some(2), 1, 2), (Some(1), 0, 2)]
+        );
+    }
+
+    #[test]
+    fn test_history_in_errors() {
+        let instructions = parse_program(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "print c",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.set_history(3);
+        let err = vm.interpret(&instructions).unwrap_err();
+        let history = err
+            .context
+            .history
+            .iter()
+            .map(|(pc, instruction)| (*pc, instruction.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            history,
+            [(3, "jnz a -1"), (2, "add a b"), (3, "jnz a -1")]
+                .map(|(pc, text)| (pc, text.to_string()))
+        );
+        assert!(vm.describe(&err).contains(
+            "\nlatest executed instructions:\n   4 | jnz a -1\n   3 | add a b\n   4 | jnz a -1"
+        ));
+    }
+
+    #[test]
+    fn test_step_without_start() {
+        let instructions = parse_instructions(vec!["mov a 1", "print b"]).unwrap();
+        let mut vm = Vm::new();
+        vm.set_history(3);
+        vm.enable_coverage();
+        assert_eq!(vm.step(&instructions).unwrap(), StepOutcome::Continued);
+        let err = vm.step(&instructions).unwrap_err();
+        assert_eq!(err.context.pc, 1);
+        assert_eq!(err.context.history, vec![(0, instructions[0].clone())]);
+        assert_eq!(err.context.state.pc, Some(1));
+        assert_eq!(vm.coverage(), Some(&[1, 1][..]));
+    }
+
+    #[test]
+    fn test_jump_history_in_errors() {
+        let instructions = parse_program(vec![
+            "mov a 3", "mov b -1", "add a b", "jnz a -1", "mov c 1", "jnz c 2", "mov a 0",
+            "out 0 a",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.set_jump_history(3);
+        let err = vm.interpret(&instructions).unwrap_err();
+        assert!(vm
+            .describe(&err)
+            .ends_with("\ncontrol reached here via jumps (line → line): 4 → 3, 4 → 3, 6 → 8"));
+    }
 }
@@ -0,0 +1,125 @@
+pub mod cfg;
+pub mod intervals;
+pub mod lint;
+pub mod liveness;
+pub mod termination;
+
+use std::collections::HashSet;
+
+use super::parser::{ConstOrReg, Instruction, Register};
+
+fn operand(x: &ConstOrReg) -> Option<&Register> {
+    match x {
+        ConstOrReg::Reg(reg) => Some(reg),
+        ConstOrReg::Const(_) => None,
+    }
+}
+
+/// Every register `instructions` name as an operand.
+pub fn registers(instructions: &[Instruction]) -> HashSet<&Register> {
+    let none = HashSet::new();
+    instructions
+        .iter()
+        .flat_map(|instruction| {
+            reads(instruction, &none)
+                .into_iter()
+                .chain(writes(instruction))
+        })
+        .collect()
+}
+
+/// Registers whose value `instruction` may use. Host functions may read any register of
+/// `program`.
+pub fn reads<'a>(
+    instruction: &'a Instruction,
+    program: &HashSet<&'a Register>,
+) -> Vec<&'a Register> {
+    match instruction {
+        Instruction::Mov(_, y) => operand(y).into_iter().collect(),
+        Instruction::Add(x, y)
+        | Instruction::Sub(x, y)
+        | Instruction::Mul(x, y)
+        | Instruction::Div(x, y)
+        | Instruction::Mod(x, y)
+        | Instruction::AddC(x, y)
+        | Instruction::SubB(x, y)
+        | Instruction::MulH(x, y)
+        | Instruction::FxMul(x, y)
+        | Instruction::FxDiv(x, y)
+        | Instruction::BIns(x, y, _) => vec![x, y],
+        Instruction::BExt(_, y, _)
+        | Instruction::Popcnt(_, y)
+        | Instruction::Clz(_, y)
+        | Instruction::Ctz(_, y) => vec![y],
+        Instruction::And(x, n)
+        | Instruction::Or(x, n)
+        | Instruction::Xor(x, n)
+        | Instruction::Shl(x, n)
+        | Instruction::Shr(x, n)
+        | Instruction::Rol(x, n)
+        | Instruction::Ror(x, n) => std::iter::once(x).chain(operand(n)).collect(),
+        Instruction::Not(x) => vec![x],
+        Instruction::Jnz(x, y) | Instruction::Jz(x, y) => {
+            operand(x).into_iter().chain(operand(y)).collect()
+        }
+        Instruction::Jmp(offset) => operand(offset).into_iter().collect(),
+        Instruction::Cmp(x, y) => std::iter::once(x).chain(operand(y)).collect(),
+        Instruction::JumpIf(_, offset) => operand(offset).into_iter().collect(),
+        Instruction::Print(x) | Instruction::Out(_, x) | Instruction::Emit(_, x) => vec![x],
+        Instruction::In(_, _) | Instruction::Poll(_, _) | Instruction::Read(_) => vec![],
+        Instruction::Syscall(_) => program.iter().copied().collect(),
+        Instruction::Custom(_, operands) => operands.iter().filter_map(operand).collect(),
+        // string registers are tracked apart from the integer ones
+        Instruction::SMov(_, _)
+        | Instruction::SCat(_, _)
+        | Instruction::SLen(_, _)
+        | Instruction::SPrint(_) => vec![],
+    }
+}
+
+/// Register assigned by `instruction`, if any.
+pub fn writes(instruction: &Instruction) -> Option<&Register> {
+    match instruction {
+        Instruction::Mov(x, _)
+        | Instruction::Add(x, _)
+        | Instruction::Sub(x, _)
+        | Instruction::Mul(x, _)
+        | Instruction::Div(x, _)
+        | Instruction::Mod(x, _)
+        | Instruction::AddC(x, _)
+        | Instruction::SubB(x, _)
+        | Instruction::MulH(x, _)
+        | Instruction::FxMul(x, _)
+        | Instruction::FxDiv(x, _)
+        | Instruction::BExt(x, _, _)
+        | Instruction::BIns(x, _, _)
+        | Instruction::And(x, _)
+        | Instruction::Or(x, _)
+        | Instruction::Xor(x, _)
+        | Instruction::Not(x)
+        | Instruction::Shl(x, _)
+        | Instruction::Shr(x, _)
+        | Instruction::Rol(x, _)
+        | Instruction::Ror(x, _)
+        | Instruction::Popcnt(x, _)
+        | Instruction::Clz(x, _)
+        | Instruction::Ctz(x, _)
+        | Instruction::In(x, _)
+        | Instruction::Poll(x, _)
+        | Instruction::Read(x)
+        | Instruction::SLen(x, _) => Some(x),
+        Instruction::Jnz(_, _)
+        | Instruction::Jz(_, _)
+        | Instruction::Jmp(_)
+        | Instruction::Cmp(_, _)
+        | Instruction::JumpIf(_, _)
+        | Instruction::Print(_)
+        | Instruction::Out(_, _)
+        | Instruction::Emit(_, _)
+        | Instruction::Syscall(_)
+        | Instruction::Custom(_, _)
+        | Instruction::SMov(_, _)
+        | Instruction::SCat(_, _)
+        | Instruction::SPrint(_) => None,
+    }
+}
@@ -0,0 +1,253 @@
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::vm::parser::{ConstOrReg, Constant, Instruction};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Successor {
+    Block(usize),
+    /// Control runs past the last instruction and the program ends.
+    Exit,
+    /// Jump target computed from a register or outside of the program.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// Exclusive end pc.
+    pub end: usize,
+    pub successors: Vec<Successor>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+}
+
+/// Absolute target of a jump with constant offset, if it stays within the program.
+pub fn jump_target(pc: usize, offset: &ConstOrReg, len: usize) -> Option<usize> {
+    match offset {
+        ConstOrReg::Const(offset) => pc
+            .checked_add_signed(**offset as isize)
+            .filter(|target| *target <= len),
+        ConstOrReg::Reg(_) => None,
+    }
+}
+
+/// Possible next pcs after `pc`, `None` standing for an unknown target.
+pub fn next_pcs(instructions: &[Instruction], pc: usize) -> Vec<Option<usize>> {
+    match &instructions[pc] {
+        Instruction::Jnz(cond, offset) => {
+            let target = jump_target(pc, offset, instructions.len());
+            match cond {
+                ConstOrReg::Const(c) if *c == Constant::ZERO => vec![Some(pc + 1)],
+                ConstOrReg::Const(_) => vec![target],
+                ConstOrReg::Reg(_) => vec![Some(pc + 1), target],
+            }
+        }
+        Instruction::Jz(cond, offset) => {
+            let target = jump_target(pc, offset, instructions.len());
+            match cond {
+                ConstOrReg::Const(c) if *c == Constant::ZERO => vec![target],
+                ConstOrReg::Const(_) => vec![Some(pc + 1)],
+                ConstOrReg::Reg(_) => vec![Some(pc + 1), target],
+            }
+        }
+        Instruction::Jmp(offset) => vec![jump_target(pc, offset, instructions.len())],
+        Instruction::JumpIf(_, offset) => {
+            vec![Some(pc + 1), jump_target(pc, offset, instructions.len())]
+        }
+        _ => vec![Some(pc + 1)],
+    }
+}
+
+impl Cfg {
+    pub fn build(instructions: &[Instruction]) -> Self {
+        let len = instructions.len();
+        let mut leaders = BTreeSet::new();
+        if len > 0 {
+            leaders.insert(0);
+        }
+        for (pc, instruction) in instructions.iter().enumerate() {
+            if let Some(offset) = instruction.jump_offset() {
+                leaders.insert(pc + 1);
+                if let Some(target) = jump_target(pc, offset, len) {
+                    leaders.insert(target);
+                }
+            }
+        }
+        let starts = leaders
+            .into_iter()
+            .filter(|pc| *pc < len)
+            .collect::<Vec<_>>();
+
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = starts.get(i + 1).copied().unwrap_or(len);
+                let mut successors = Vec::new();
+                for next in next_pcs(instructions, end - 1) {
+                    let successor = match next {
+                        Some(pc) if pc == len => Successor::Exit,
+                        Some(pc) => Successor::Block(starts.binary_search(&pc).unwrap()),
+                        None => Successor::Unknown,
+                    };
+                    if !successors.contains(&successor) {
+                        successors.push(successor);
+                    }
+                }
+                BasicBlock {
+                    start,
+                    end,
+                    successors,
+                }
+            })
+            .collect();
+        Cfg { blocks }
+    }
+
+    pub fn predecessors(&self, block: usize) -> Vec<usize> {
+        (0..self.blocks.len())
+            .filter(|i| {
+                self.blocks[*i]
+                    .successors
+                    .contains(&Successor::Block(block))
+            })
+            .collect()
+    }
+
+    /// Blocks that can run when starting at pc 0, a jump to an unknown target
+    /// is assumed to reach every block.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = if self.blocks.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(i) = stack.pop() {
+            if reachable[i] {
+                continue;
+            }
+            reachable[i] = true;
+            for successor in &self.blocks[i].successors {
+                match successor {
+                    Successor::Block(j) => stack.push(*j),
+                    Successor::Unknown => stack.extend(0..self.blocks.len()),
+                    Successor::Exit => (),
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Renders the graph in Graphviz dot format, one node per block listing its instructions by
+    /// line number.
+    pub fn to_dot(&self, instructions: &[Instruction]) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for (pc, instruction) in instructions[block.start..block.end].iter().enumerate() {
+                let text = instruction
+                    .to_string()
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"");
+                write!(label, "{}: {text}\\l", block.start + pc + 1).unwrap();
+            }
+            writeln!(dot, "    b{i} [label=\"{label}\"];").unwrap();
+        }
+        let mut exit = false;
+        let mut unknown = false;
+        for (i, block) in self.blocks.iter().enumerate() {
+            for successor in &block.successors {
+                match successor {
+                    Successor::Block(j) => writeln!(dot, "    b{i} -> b{j};").unwrap(),
+                    Successor::Exit => {
+                        exit = true;
+                        writeln!(dot, "    b{i} -> exit;").unwrap()
+                    }
+                    Successor::Unknown => {
+                        unknown = true;
+                        writeln!(dot, "    b{i} -> unknown [style=dashed];").unwrap()
+                    }
+                }
+            }
+        }
+        if exit {
+            dot.push_str("    exit [shape=oval];\n");
+        }
+        if unknown {
+            dot.push_str("    unknown [shape=oval, label=\"?\"];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+
+    #[test]
+    fn test_blocks_and_edges() {
+        let instructions = parse_instructions(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "print a",
+        ])
+        .unwrap();
+        let cfg = Cfg::build(&instructions);
+        assert_eq!(cfg.predecessors(1), vec![0, 1]);
+        assert_eq!(
+            cfg.blocks,
+            vec![
+                BasicBlock {
+                    start: 0,
+                    end: 2,
+                    successors: vec![Successor::Block(1)],
+                },
+                BasicBlock {
+                    start: 2,
+                    end: 4,
+                    successors: vec![Successor::Block(2), Successor::Block(1)],
+                },
+                BasicBlock {
+                    start: 4,
+                    end: 5,
+                    successors: vec![Successor::Exit],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_constant_and_register_jumps() {
+        let instructions = parse_instructions(vec!["jnz 1 2", "jnz 0 a", "jnz a b"]).unwrap();
+        let cfg = Cfg::build(&instructions);
+        assert_eq!(cfg.blocks[0].successors, vec![Successor::Block(2)]);
+        assert_eq!(cfg.blocks[1].successors, vec![Successor::Block(2)]);
+        assert_eq!(
+            cfg.blocks[2].successors,
+            vec![Successor::Exit, Successor::Unknown]
+        );
+        let instructions = parse_instructions(vec!["jz 0 2", "jz 1 a", "jmp -2"]).unwrap();
+        let cfg = Cfg::build(&instructions);
+        assert_eq!(cfg.blocks[0].successors, vec![Successor::Block(2)]);
+        assert_eq!(cfg.blocks[1].successors, vec![Successor::Block(2)]);
+        assert_eq!(cfg.blocks[2].successors, vec![Successor::Block(0)]);
+    }
+
+    #[test]
+    fn test_dot_output() {
+        let instructions = parse_instructions(vec!["mov a 1", "jnz a b"]).unwrap();
+        let dot = Cfg::build(&instructions).to_dot(&instructions);
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b0 [label=\"1: mov a 1\\l2: jnz a b\\l\"];"));
+        assert!(dot.contains("b0 -> exit;"));
+        assert!(dot.contains("b0 -> unknown [style=dashed];"));
+
+        let instructions = parse_instructions(vec![r#"smov s "a\\b""#]).unwrap();
+        let dot = Cfg::build(&instructions).to_dot(&instructions);
+        assert!(dot.contains(r#"b0 [label="1: smov s \"a\\\\b\"\l"];"#));
+    }
+}
@@ -0,0 +1,702 @@
+use std::{collections::HashMap, fmt::Display};
+
+use super::cfg::jump_target;
+use super::lint::{Diagnostic, Severity};
+use crate::vm::parser::{ConstOrReg, Instruction, Register};
+
+/// Inclusive range of values a register can hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: i32,
+    pub hi: i32,
+}
+
+impl Interval {
+    pub const TOP: Interval = Interval {
+        lo: i32::MIN,
+        hi: i32::MAX,
+    };
+
+    pub fn constant(c: i32) -> Self {
+        Interval { lo: c, hi: c }
+    }
+
+    fn join(self, other: Interval) -> Interval {
+        Interval {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// Moves bounds that keep growing out to the next threshold, so loops reach a fixed point.
+    /// `thresholds` is sorted and holds `i32::MIN` and `i32::MAX`.
+    fn widen(self, next: Interval, thresholds: &[i32]) -> Interval {
+        let lo = if next.lo < self.lo {
+            *thresholds.iter().rev().find(|t| **t <= next.lo).unwrap()
+        } else {
+            self.lo
+        };
+        let hi = if next.hi > self.hi {
+            *thresholds.iter().find(|t| **t >= next.hi).unwrap()
+        } else {
+            self.hi
+        };
+        Interval { lo, hi }
+    }
+
+    fn contains(self, value: i32) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    /// Sum of two ranges, `None` when the addition can overflow.
+    fn add(self, other: Interval) -> Option<Interval> {
+        let lo = i32::try_from(self.lo as i64 + other.lo as i64).ok()?;
+        let hi = i32::try_from(self.hi as i64 + other.hi as i64).ok()?;
+        Some(Interval { lo, hi })
+    }
+
+    /// Difference of two ranges, `None` when the subtraction can overflow.
+    fn sub(self, other: Interval) -> Option<Interval> {
+        self.corners(other, |a, b| a - b)
+    }
+
+    /// Product of two ranges, `None` when the multiplication can overflow.
+    fn mul(self, other: Interval) -> Option<Interval> {
+        self.corners(other, |a, b| a * b)
+    }
+
+    /// Range of the remainder of dividing by a range without zero, which is smaller than
+    /// the divisor and has the sign of the dividend.
+    fn rem(self, other: Interval) -> Interval {
+        let max = (other.lo as i64).abs().max((other.hi as i64).abs()) - 1;
+        let max = max.min(i32::MAX as i64) as i32;
+        Interval {
+            lo: if self.lo >= 0 { 0 } else { -max },
+            hi: if self.hi <= 0 { 0 } else { max },
+        }
+    }
+
+    /// Range of the bitwise and of two ranges: a non-negative operand bounds the result.
+    fn and(self, other: Interval) -> Interval {
+        match (self.lo >= 0, other.lo >= 0) {
+            (true, true) => Interval {
+                lo: 0,
+                hi: self.hi.min(other.hi),
+            },
+            (true, false) => Interval { lo: 0, hi: self.hi },
+            (false, true) => Interval {
+                lo: 0,
+                hi: other.hi,
+            },
+            (false, false) => Interval::TOP,
+        }
+    }
+
+    /// Range of a logical right shift by a range of amounts, which only bounds the result
+    /// when every amount shifts in at least one zero.
+    fn shr(self, amount: Interval) -> Interval {
+        if amount.lo < 1 {
+            return Interval::TOP;
+        }
+        let hi = u32::MAX.checked_shr(amount.lo as u32).unwrap_or(0);
+        Interval {
+            lo: 0,
+            hi: hi as i32,
+        }
+    }
+
+    /// Smallest range holding `op` applied to every pair of bounds, `None` if it leaves `i32`.
+    /// Only sound for operations that are monotonic in each argument.
+    fn corners(self, other: Interval, op: impl Fn(i64, i64) -> i64) -> Option<Interval> {
+        let values = [
+            op(self.lo as i64, other.lo as i64),
+            op(self.lo as i64, other.hi as i64),
+            op(self.hi as i64, other.lo as i64),
+            op(self.hi as i64, other.hi as i64),
+        ];
+        let lo = i32::try_from(*values.iter().min().unwrap()).ok()?;
+        let hi = i32::try_from(*values.iter().max().unwrap()).ok()?;
+        Some(Interval { lo, hi })
+    }
+
+    /// Q16.16 product of two ranges, `None` when it can overflow.
+    fn fx_mul(self, other: Interval) -> Option<Interval> {
+        self.corners(other, |a, b| (a * b) >> 16)
+    }
+
+    /// Q16.16 quotient of two ranges, `None` when it can overflow or divide by zero.
+    fn fx_div(self, other: Interval) -> Option<Interval> {
+        if other.contains(0) {
+            return None;
+        }
+        self.corners(other, |a, b| (a << 16) / b)
+    }
+
+    /// Range left after learning that the value is not zero.
+    fn non_zero(self) -> Interval {
+        Interval {
+            lo: if self.lo == 0 { 1 } else { self.lo },
+            hi: if self.hi == 0 { -1 } else { self.hi },
+        }
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+pub type Ranges = HashMap<Register, Interval>;
+
+const WIDEN_AFTER: usize = 3;
+
+fn load(state: &Ranges, x: &ConstOrReg) -> Option<Interval> {
+    match x {
+        ConstOrReg::Const(c) => Some(Interval::constant(**c)),
+        ConstOrReg::Reg(reg) => state.get(reg).copied(),
+    }
+}
+
+/// States flowing out of `pc` paired with the pc they flow to. Instructions that stop the
+/// VM, like reading an uninitialised register, have no successors.
+fn transfer(instructions: &[Instruction], pc: usize, state: &Ranges) -> Vec<(usize, Ranges)> {
+    let len = instructions.len();
+    let mut state = state.clone();
+    match &instructions[pc] {
+        Instruction::Mov(x, y) => match load(&state, y) {
+            Some(value) => {
+                state.insert(x.clone(), value);
+            }
+            None => return vec![],
+        },
+        Instruction::Add(x, y) => match (state.get(x), state.get(y)) {
+            (Some(a), Some(b)) => {
+                let sum = a.add(*b).unwrap_or(Interval::TOP);
+                state.insert(x.clone(), sum);
+            }
+            _ => return vec![],
+        },
+        Instruction::Sub(x, y) => match (state.get(x), state.get(y)) {
+            (Some(a), Some(b)) => {
+                let difference = a.sub(*b).unwrap_or(Interval::TOP);
+                state.insert(x.clone(), difference);
+            }
+            _ => return vec![],
+        },
+        Instruction::Mul(x, y) => match (state.get(x), state.get(y)) {
+            (Some(a), Some(b)) => {
+                let product = a.mul(*b).unwrap_or(Interval::TOP);
+                state.insert(x.clone(), product);
+            }
+            _ => return vec![],
+        },
+        // dividing by zero stops the VM
+        Instruction::Div(x, y) | Instruction::Mod(x, y) => match (state.get(x), state.get(y)) {
+            (Some(_), Some(b)) if *b == Interval::constant(0) => return vec![],
+            (Some(a), Some(b)) => {
+                let range = match &instructions[pc] {
+                    Instruction::Mod(..) => a.rem(b.non_zero()),
+                    _ => Interval::TOP,
+                };
+                state.insert(x.clone(), range);
+            }
+            _ => return vec![],
+        },
+        Instruction::Print(x) | Instruction::Out(_, x) | Instruction::Emit(_, x)
+            if !state.contains_key(x) =>
+        {
+            return vec![]
+        }
+        Instruction::Print(_) | Instruction::Out(_, _) | Instruction::Emit(_, _) => (),
+        Instruction::In(x, _) => {
+            state.insert(x.clone(), Interval::TOP);
+        }
+        Instruction::Poll(x, _) => {
+            state.insert(x.clone(), Interval { lo: 0, hi: 1 });
+        }
+        Instruction::Read(x) => {
+            state.insert(x.clone(), Interval { lo: -1, hi: 255 });
+        }
+        Instruction::Syscall(_) | Instruction::Custom(_, _) => {
+            state.values_mut().for_each(|range| *range = Interval::TOP)
+        }
+        Instruction::FxMul(x, y) => match (state.get(x), state.get(y)) {
+            (Some(a), Some(b)) => {
+                let product = a.fx_mul(*b).unwrap_or(Interval::TOP);
+                state.insert(x.clone(), product);
+            }
+            _ => return vec![],
+        },
+        // dividing by zero stops the VM
+        Instruction::FxDiv(x, y) => match (state.get(x), state.get(y)) {
+            (Some(_), Some(b)) if *b == Interval::constant(0) => return vec![],
+            (Some(a), Some(b)) => {
+                let quotient = a.fx_div(b.non_zero()).unwrap_or(Interval::TOP);
+                state.insert(x.clone(), quotient);
+            }
+            _ => return vec![],
+        },
+        Instruction::BExt(x, y, field) if state.contains_key(y) => {
+            let hi = field.mask() >> field.offset;
+            let range = match i32::try_from(hi) {
+                Ok(hi) => Interval { lo: 0, hi },
+                Err(_) => Interval::TOP,
+            };
+            state.insert(x.clone(), range);
+        }
+        Instruction::BIns(x, y, _) if state.contains_key(x) && state.contains_key(y) => {
+            state.insert(x.clone(), Interval::TOP);
+        }
+        Instruction::And(x, n) | Instruction::Shr(x, n) => match (state.get(x), load(&state, n)) {
+            (Some(a), Some(b)) => {
+                let range = match &instructions[pc] {
+                    Instruction::And(..) => a.and(b),
+                    _ => a.shr(b),
+                };
+                state.insert(x.clone(), range);
+            }
+            _ => return vec![],
+        },
+        Instruction::Or(x, n)
+        | Instruction::Xor(x, n)
+        | Instruction::Shl(x, n)
+        | Instruction::Rol(x, n)
+        | Instruction::Ror(x, n)
+            if state.contains_key(x) && load(&state, n).is_some() =>
+        {
+            state.insert(x.clone(), Interval::TOP);
+        }
+        Instruction::Not(x) => match state.get(x) {
+            Some(a) => {
+                let range = Interval {
+                    lo: !a.hi,
+                    hi: !a.lo,
+                };
+                state.insert(x.clone(), range);
+            }
+            None => return vec![],
+        },
+        Instruction::Popcnt(x, y) | Instruction::Clz(x, y) | Instruction::Ctz(x, y)
+            if state.contains_key(y) =>
+        {
+            state.insert(x.clone(), Interval { lo: 0, hi: 32 });
+        }
+        Instruction::AddC(x, y) | Instruction::SubB(x, y) | Instruction::MulH(x, y)
+            if state.contains_key(x) && state.contains_key(y) =>
+        {
+            state.insert(x.clone(), Interval::TOP);
+        }
+        Instruction::AddC(_, _) | Instruction::SubB(_, _) | Instruction::MulH(_, _) => {
+            return vec![]
+        }
+        Instruction::BExt(_, _, _)
+        | Instruction::BIns(_, _, _)
+        | Instruction::Popcnt(_, _)
+        | Instruction::Clz(_, _)
+        | Instruction::Ctz(_, _)
+        | Instruction::Or(_, _)
+        | Instruction::Xor(_, _)
+        | Instruction::Shl(_, _)
+        | Instruction::Rol(_, _)
+        | Instruction::Ror(_, _) => return vec![],
+        Instruction::SLen(x, _) => {
+            state.insert(
+                x.clone(),
+                Interval {
+                    lo: 0,
+                    hi: i32::MAX,
+                },
+            );
+        }
+        Instruction::SMov(_, _) | Instruction::SCat(_, _) | Instruction::SPrint(_) => (),
+        Instruction::Jnz(x, y) | Instruction::Jz(x, y) => {
+            let Some(cond) = load(&state, x) else {
+                return vec![];
+            };
+            let on_zero = matches!(instructions[pc], Instruction::Jz(..));
+            let mut next = Vec::new();
+            let mut follow = |state: Ranges, jumps| {
+                if jumps {
+                    for target in jump_targets(&state, pc, y, len) {
+                        next.push((target, state.clone()));
+                    }
+                } else {
+                    next.push((pc + 1, state));
+                }
+            };
+            if cond.contains(0) {
+                let mut zero = state.clone();
+                if let ConstOrReg::Reg(reg) = x {
+                    zero.insert(reg.clone(), Interval::constant(0));
+                }
+                follow(zero, on_zero);
+            }
+            if cond != Interval::constant(0) {
+                if let ConstOrReg::Reg(reg) = x {
+                    state.insert(reg.clone(), cond.non_zero());
+                }
+                follow(state, !on_zero);
+            }
+            return next;
+        }
+        Instruction::Jmp(offset) => {
+            return jump_targets(&state, pc, offset, len)
+                .into_iter()
+                .map(|target| (target, state.clone()))
+                .collect()
+        }
+        Instruction::Cmp(x, y) if state.contains_key(x) && load(&state, y).is_some() => (),
+        Instruction::Cmp(_, _) => return vec![],
+        // the flags aren't tracked, so both ways are possible
+        Instruction::JumpIf(_, offset) => {
+            let mut next = vec![(pc + 1, state.clone())];
+            for target in jump_targets(&state, pc, offset, len) {
+                next.push((target, state.clone()));
+            }
+            return next;
+        }
+    }
+    vec![(pc + 1, state)]
+}
+
+/// Pcs a jump from `pc` by `offset` can reach within the program.
+fn jump_targets(state: &Ranges, pc: usize, offset: &ConstOrReg, len: usize) -> Vec<usize> {
+    match load(state, offset) {
+        Some(offset) => {
+            // only offsets that stay within 0..=len are worth walking
+            let lo = (offset.lo as i64).max(-(pc as i64));
+            let hi = (offset.hi as i64).min((len - pc) as i64);
+            (lo..=hi)
+                .map(|offset| (pc as i64 + offset) as usize)
+                .collect()
+        }
+        None => vec![],
+    }
+}
+
+fn merge(current: &Ranges, incoming: &Ranges, widen: Option<&[i32]>) -> Ranges {
+    let mut merged = current.clone();
+    for (reg, value) in incoming {
+        let joined = match (current.get(reg), widen) {
+            (Some(old), Some(thresholds)) => old.widen(old.join(*value), thresholds),
+            (Some(old), None) => old.join(*value),
+            (None, _) => *value,
+        };
+        merged.insert(reg.clone(), joined);
+    }
+    merged
+}
+
+/// Bounds worth stopping at while widening: the program constants and the values around zero.
+fn thresholds(instructions: &[Instruction]) -> Vec<i32> {
+    let mut thresholds = vec![i32::MIN, -1, 0, 1, i32::MAX];
+    for instruction in instructions {
+        if let Instruction::Mov(_, ConstOrReg::Const(c))
+        | Instruction::Jnz(_, ConstOrReg::Const(c))
+        | Instruction::Cmp(_, ConstOrReg::Const(c)) = instruction
+        {
+            thresholds.push(**c);
+        }
+    }
+    thresholds.sort();
+    thresholds.dedup();
+    thresholds
+}
+
+/// Possible register values on entry to every pc, `None` for pcs that are never reached.
+pub fn register_ranges(instructions: &[Instruction]) -> Vec<Option<Ranges>> {
+    let len = instructions.len();
+    let mut states: Vec<Option<Ranges>> = vec![None; len];
+    let mut visits = vec![0; len];
+    if len == 0 {
+        return states;
+    }
+    let thresholds = thresholds(instructions);
+    states[0] = Some(HashMap::new());
+    let mut worklist = vec![0];
+    while let Some(pc) = worklist.pop() {
+        let Some(state) = states[pc].clone() else {
+            continue;
+        };
+        for (next, out) in transfer(instructions, pc, &state) {
+            if next >= len {
+                continue;
+            }
+            let merged = match &states[next] {
+                Some(current) => {
+                    let widen = (visits[next] >= WIDEN_AFTER).then_some(thresholds.as_slice());
+                    merge(current, &out, widen)
+                }
+                None => out,
+            };
+            if states[next].as_ref() != Some(&merged) {
+                states[next] = Some(merged);
+                visits[next] += 1;
+                worklist.push(next);
+            }
+        }
+    }
+    states
+}
+
+/// Warnings for operations that can fail or overflow given the inferred ranges.
+pub fn range_warnings(instructions: &[Instruction]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (pc, state) in register_ranges(instructions).iter().enumerate() {
+        let Some(state) = state else {
+            continue;
+        };
+        let mut warn = |severity, message| {
+            diagnostics.push(Diagnostic {
+                severity,
+                pc,
+                message,
+            })
+        };
+        match &instructions[pc] {
+            Instruction::Print(x) => match state.get(x) {
+                Some(range) if range.hi < 0 => warn(
+                    Severity::Error,
+                    format!("register {x} is always negative {range}, it cannot be printed"),
+                ),
+                Some(range) if range.lo < 0 => warn(
+                    Severity::Warning,
+                    format!("register {x} may be negative {range}, it cannot be printed"),
+                ),
+                _ => (),
+            },
+            Instruction::Add(x, y) => {
+                if let (Some(a), Some(b)) = (state.get(x), state.get(y)) {
+                    if a.add(*b).is_none() {
+                        warn(
+                            Severity::Warning,
+                            format!("add {x} {y} may overflow, adding {a} and {b}"),
+                        );
+                    }
+                }
+            }
+            Instruction::Sub(x, y) => {
+                if let (Some(a), Some(b)) = (state.get(x), state.get(y)) {
+                    if a.sub(*b).is_none() {
+                        warn(
+                            Severity::Warning,
+                            format!("sub {x} {y} may overflow, subtracting {b} from {a}"),
+                        );
+                    }
+                }
+            }
+            Instruction::Mul(x, y) => {
+                if let (Some(a), Some(b)) = (state.get(x), state.get(y)) {
+                    if a.mul(*b).is_none() {
+                        warn(
+                            Severity::Warning,
+                            format!("mul {x} {y} may overflow, multiplying {a} and {b}"),
+                        );
+                    }
+                }
+            }
+            Instruction::FxMul(x, y) => {
+                if let (Some(a), Some(b)) = (state.get(x), state.get(y)) {
+                    if a.fx_mul(*b).is_none() {
+                        warn(
+                            Severity::Warning,
+                            format!("fxmul {x} {y} may overflow, multiplying {a} and {b}"),
+                        );
+                    }
+                }
+            }
+            instruction @ (Instruction::Div(_, y)
+            | Instruction::Mod(_, y)
+            | Instruction::FxDiv(_, y)) => match state.get(y) {
+                Some(range) if *range == Interval::constant(0) => warn(
+                    Severity::Error,
+                    format!(
+                        "register {y} is always zero, {} traps",
+                        instruction.opcode()
+                    ),
+                ),
+                Some(range) if range.contains(0) => warn(
+                    Severity::Warning,
+                    format!(
+                        "register {y} may be zero {range}, {} may trap",
+                        instruction.opcode()
+                    ),
+                ),
+                _ => (),
+            },
+            Instruction::Jnz(_, y @ ConstOrReg::Reg(reg))
+            | Instruction::Jz(_, y @ ConstOrReg::Reg(reg))
+            | Instruction::Jmp(y @ ConstOrReg::Reg(reg))
+            | Instruction::JumpIf(_, y @ ConstOrReg::Reg(reg)) => {
+                if let Some(offset) = load(state, y) {
+                    let leaves = [offset.lo, offset.hi].iter().any(|offset| {
+                        jump_target(pc, &ConstOrReg::Const((*offset).into()), instructions.len())
+                            .is_none()
+                    });
+                    if leaves {
+                        warn(
+                            Severity::Warning,
+                            format!("jump by register {reg} in {offset} may leave the program"),
+                        );
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+
+    fn range_at(input: Vec<&str>, pc: usize, reg: &str) -> Option<Interval> {
+        let ranges = register_ranges(&parse_instructions(input).unwrap());
+        ranges[pc]
+            .as_ref()
+            .and_then(|state| state.get(&Register::of(reg.to_string())).copied())
+    }
+
+    #[test]
+    fn test_countdown_loop() {
+        let program = vec!["mov a 10", "mov b -1", "add a b", "jnz a -1", "print a"];
+        assert_eq!(
+            range_at(program.clone(), 2, "a"),
+            Some(Interval { lo: 1, hi: 10 })
+        );
+        assert_eq!(range_at(program, 4, "a"), Some(Interval::constant(0)));
+    }
+
+    #[test]
+    fn test_widening_terminates() {
+        // counting up forever wraps around, so every value is possible
+        let program = vec!["mov a 0", "mov b 1", "add a b", "jnz 1 -1"];
+        assert_eq!(range_at(program, 2, "a"), Some(Interval::TOP));
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        let program = vec![
+            "poll a 0",
+            "mov b 131072",
+            "fxmul a b",
+            "mov c -65536",
+            "fxdiv a c",
+            "fxdiv c a",
+        ];
+        assert_eq!(
+            range_at(program.clone(), 3, "a"),
+            Some(Interval { lo: 0, hi: 2 })
+        );
+        assert_eq!(
+            range_at(program.clone(), 5, "a"),
+            Some(Interval { lo: -2, hi: 0 })
+        );
+        let instructions = parse_instructions(program).unwrap();
+        let messages = range_warnings(&instructions)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec!["line 6: warning: register a may be zero [-2, 0], fxdiv may trap"]
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let program = vec![
+            "poll a 0", "mov b 3", "sub b a", "mul a b", "in c 0", "mod c b", "div c a",
+        ];
+        assert_eq!(
+            range_at(program.clone(), 3, "b"),
+            Some(Interval { lo: 2, hi: 3 })
+        );
+        assert_eq!(
+            range_at(program.clone(), 4, "a"),
+            Some(Interval { lo: 0, hi: 3 })
+        );
+        assert_eq!(
+            range_at(program.clone(), 6, "c"),
+            Some(Interval { lo: -2, hi: 2 })
+        );
+        let instructions = parse_instructions(program).unwrap();
+        let messages = range_warnings(&instructions)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec!["line 7: warning: register a may be zero [0, 3], div may trap"]
+        );
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let program = vec![
+            "in a 0", "mov b a", "mov c a", "and a 15", "shr b 24", "not a", "shr c a", "print a",
+        ];
+        assert_eq!(
+            range_at(program.clone(), 4, "a"),
+            Some(Interval { lo: 0, hi: 15 })
+        );
+        assert_eq!(
+            range_at(program.clone(), 5, "b"),
+            Some(Interval { lo: 0, hi: 255 })
+        );
+        assert_eq!(
+            range_at(program.clone(), 6, "a"),
+            Some(Interval { lo: -16, hi: -1 })
+        );
+        assert_eq!(range_at(program, 7, "c"), Some(Interval::TOP));
+    }
+
+    #[test]
+    fn test_bit_fields() {
+        let program = vec![
+            "in a 0",
+            "bext b a 4 3",
+            "bext c a 0 32",
+            "bins b a 1 2",
+            "print b",
+        ];
+        assert_eq!(
+            range_at(program.clone(), 2, "b"),
+            Some(Interval { lo: 0, hi: 7 })
+        );
+        assert_eq!(range_at(program.clone(), 3, "c"), Some(Interval::TOP));
+        assert_eq!(range_at(program, 4, "b"), Some(Interval::TOP));
+    }
+
+    #[test]
+    fn test_unknown_jump_offset() {
+        // only the pcs inside the program are visited, not every possible offset
+        let program = vec!["in a 0", "jnz 1 a"];
+        assert_eq!(range_at(program.clone(), 0, "a"), Some(Interval::TOP));
+        assert_eq!(range_at(program, 1, "a"), Some(Interval::TOP));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let instructions = parse_instructions(vec![
+            "poll a 0", "mov b -1", "add a b", "print a", "in c 0", "add c a", "poll d 0",
+            "mov e 2", "add d e", "jnz 1 d",
+        ])
+        .unwrap();
+        let messages = range_warnings(&instructions)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "line 4: warning: register a may be negative [-1, 0], it cannot be printed",
+                "line 6: warning: add c a may overflow, adding [-2147483648, 2147483647] and [-1, 0]",
+                "line 10: warning: jump by register d in [2, 3] may leave the program",
+            ]
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use super::cfg::{jump_target, Cfg, Successor};
+use super::intervals::range_warnings;
+use super::{reads, registers, writes};
+use crate::vm::parser::{ConstOrReg, Constant, Instruction, Register};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub pc: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // every source line holds exactly one instruction
+        write!(
+            f,
+            "line {}: {}: {}",
+            self.pc + 1,
+            self.severity,
+            self.message
+        )
+    }
+}
+
+/// Registers `instruction` initializes on every path (`must`) or on some path. Host functions
+/// may set any register of `program` but don't have to, and an extension may write any of its
+/// register operands.
+fn initializes<'a>(
+    instruction: &'a Instruction,
+    program: &HashSet<&'a Register>,
+    must: bool,
+) -> Vec<&'a Register> {
+    match instruction {
+        Instruction::Syscall(_) if must => vec![],
+        Instruction::Syscall(_) => program.iter().copied().collect(),
+        Instruction::Custom(_, operands) => operands
+            .iter()
+            .filter_map(|operand| match operand {
+                ConstOrReg::Reg(reg) => Some(reg),
+                ConstOrReg::Const(_) => None,
+            })
+            .collect(),
+        _ => writes(instruction).into_iter().collect(),
+    }
+}
+
+/// Registers initialized on entry to each block, either on every path (`must`)
+/// or on at least one path (`may`) leading there.
+fn initialized(
+    cfg: &Cfg,
+    instructions: &[Instruction],
+    must: bool,
+) -> Vec<Option<HashSet<Register>>> {
+    let program = registers(instructions);
+    let unknown_sources = (0..cfg.blocks.len())
+        .filter(|i| cfg.blocks[*i].successors.contains(&Successor::Unknown))
+        .collect::<Vec<_>>();
+    let preds = (0..cfg.blocks.len())
+        .map(|i| {
+            let mut preds = cfg.predecessors(i);
+            preds.extend(&unknown_sources);
+            preds
+        })
+        .collect::<Vec<_>>();
+
+    // `None` is "not computed yet", the identity for intersection and union alike
+    let mut entry: Vec<Option<HashSet<Register>>> = vec![None; cfg.blocks.len()];
+    if !cfg.blocks.is_empty() {
+        entry[0] = Some(HashSet::new());
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..cfg.blocks.len() {
+            let mut state = if i == 0 { entry[0].clone() } else { None };
+            for &p in &preds[i] {
+                let Some(mut out) = entry[p].clone() else {
+                    continue;
+                };
+                let block = &cfg.blocks[p];
+                out.extend(
+                    instructions[block.start..block.end]
+                        .iter()
+                        .flat_map(|instruction| initializes(instruction, &program, must))
+                        .cloned(),
+                );
+                state = Some(match state {
+                    None => out,
+                    Some(state) if must => state.intersection(&out).cloned().collect(),
+                    Some(state) => state.union(&out).cloned().collect(),
+                });
+            }
+            if state != entry[i] {
+                entry[i] = state;
+                changed = true;
+            }
+        }
+    }
+    entry
+}
+
+/// Runs every check over the program, including the range warnings from interval analysis,
+/// returning diagnostics ordered by pc.
+pub fn lint(instructions: &[Instruction]) -> Vec<Diagnostic> {
+    let cfg = Cfg::build(instructions);
+    let reachable = cfg.reachable();
+    let must = initialized(&cfg, instructions, true);
+    let may = initialized(&cfg, instructions, false);
+    let program = registers(instructions);
+    let read = instructions
+        .iter()
+        .flat_map(|instruction| reads(instruction, &program))
+        .collect::<HashSet<_>>();
+    let mut diagnostics = Vec::new();
+
+    for (i, block) in cfg.blocks.iter().enumerate() {
+        if !reachable[i] {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                pc: block.start,
+                message: format!("instructions up to line {} are unreachable", block.end),
+            });
+            continue;
+        }
+        let mut must = must[i].clone().unwrap_or_default();
+        let mut may = may[i].clone().unwrap_or_default();
+        for (pc, instruction) in instructions
+            .iter()
+            .enumerate()
+            .take(block.end)
+            .skip(block.start)
+        {
+            let mut reported = HashSet::new();
+            // host functions don't have to read anything and operands of an extension may
+            // be outputs, so they are not checked
+            let checked = match instruction {
+                Instruction::Syscall(_) | Instruction::Custom(_, _) => vec![],
+                _ => reads(instruction, &program),
+            };
+            for reg in checked {
+                if must.contains(reg) || !reported.insert(reg) {
+                    continue;
+                }
+                let (severity, message) = if may.contains(reg) {
+                    (
+                        Severity::Warning,
+                        format!("register {reg} may be read before it is initialized"),
+                    )
+                } else {
+                    (
+                        Severity::Error,
+                        format!("register {reg} is read before it is initialized"),
+                    )
+                };
+                diagnostics.push(Diagnostic {
+                    severity,
+                    pc,
+                    message,
+                });
+            }
+            must.extend(
+                initializes(instruction, &program, true)
+                    .into_iter()
+                    .cloned(),
+            );
+            may.extend(
+                initializes(instruction, &program, false)
+                    .into_iter()
+                    .cloned(),
+            );
+            let jump = match instruction {
+                Instruction::Jnz(cond, offset) => Some((Taken::NonZero(cond), offset)),
+                Instruction::Jz(cond, offset) => Some((Taken::Zero(cond), offset)),
+                Instruction::JumpIf(_, offset) => Some((Taken::Flags, offset)),
+                Instruction::Jmp(offset) => Some((Taken::Always, offset)),
+                _ => None,
+            };
+            if let Some((taken, offset)) = jump {
+                diagnostics.extend(check_jump(pc, taken, offset, instructions.len()));
+            }
+        }
+    }
+
+    let mut first_write = HashMap::new();
+    for (pc, instruction) in instructions.iter().enumerate() {
+        if let Some(reg) = writes(instruction) {
+            first_write.entry(reg).or_insert(pc);
+        }
+    }
+    for (reg, pc) in first_write {
+        if !read.contains(reg) {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                pc,
+                message: format!("register {reg} is written but never read"),
+            });
+        }
+    }
+
+    diagnostics.extend(range_warnings(instructions));
+
+    diagnostics.sort_by(|a, b| a.pc.cmp(&b.pc).then(b.severity.cmp(&a.severity)));
+    diagnostics
+}
+
+/// When a jump is taken.
+enum Taken<'a> {
+    NonZero(&'a ConstOrReg),
+    Zero(&'a ConstOrReg),
+    /// Depending on the flags of `cmp`.
+    Flags,
+    Always,
+}
+
+fn check_jump(pc: usize, taken: Taken, offset: &ConstOrReg, len: usize) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let diagnostic = |severity, message| Diagnostic {
+        severity,
+        pc,
+        message,
+    };
+    let mut never_jumps = false;
+    match taken {
+        Taken::NonZero(ConstOrReg::Const(c)) | Taken::Zero(ConstOrReg::Const(c)) => {
+            never_jumps = matches!(taken, Taken::NonZero(_)) == (*c == Constant::ZERO);
+            let message = match taken {
+                _ if !never_jumps => format!("condition {c} is constant, the jump is always taken"),
+                Taken::NonZero(_) => {
+                    "condition is always zero, the jump is never taken".to_string()
+                }
+                _ => format!("condition {c} is never zero, the jump is never taken"),
+            };
+            let severity = if never_jumps {
+                Severity::Warning
+            } else {
+                Severity::Info
+            };
+            diagnostics.push(diagnostic(severity, message));
+        }
+        _ => (),
+    }
+    match offset {
+        ConstOrReg::Const(_) if never_jumps => (),
+        ConstOrReg::Const(offset) if *offset == Constant::ZERO => {
+            let (severity, message) = match taken {
+                Taken::NonZero(ConstOrReg::Reg(reg)) => (
+                    Severity::Warning,
+                    format!("jump to itself loops forever unless register {reg} is zero"),
+                ),
+                Taken::Zero(ConstOrReg::Reg(reg)) => (
+                    Severity::Warning,
+                    format!("jump to itself loops forever unless register {reg} is not zero"),
+                ),
+                Taken::Flags => (
+                    Severity::Warning,
+                    "jump to itself loops forever once taken".to_string(),
+                ),
+                _ => (Severity::Error, "jump to itself loops forever".to_string()),
+            };
+            diagnostics.push(diagnostic(severity, message));
+        }
+        ConstOrReg::Const(c) if jump_target(pc, offset, len).is_none() => {
+            diagnostics.push(diagnostic(
+                Severity::Error,
+                format!("jump by {c} leaves the program"),
+            ));
+        }
+        _ => (),
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+
+    fn messages(input: Vec<&str>) -> Vec<String> {
+        lint(&parse_instructions(input).unwrap())
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_program() {
+        assert!(messages(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "print a"
+        ])
+        .is_empty());
+    }
+
+    #[test]
+    fn test_uninitialized_reads() {
+        assert_eq!(
+            messages(vec!["in a 0", "jnz a 2", "mov b 1", "print b", "print c"]),
+            vec![
+                "line 4: warning: register b may be read before it is initialized",
+                "line 5: error: register c is read before it is initialized",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_writes() {
+        assert_eq!(
+            messages(vec!["syscall 1", "print a", "print b", "mov b 1"]),
+            vec![
+                "line 2: warning: register a may be read before it is initialized",
+                "line 3: warning: register b may be read before it is initialized",
+            ]
+        );
+        let mut instructions = parse_instructions(vec!["print b"]).unwrap();
+        let b = ConstOrReg::Reg(Register::of("b".to_string()));
+        instructions.insert(0, Instruction::Custom("rand".to_string(), vec![b]));
+        assert!(lint(&instructions).is_empty());
+    }
+
+    #[test]
+    fn test_host_reads() {
+        assert!(messages(vec!["mov a 9", "syscall 1"]).is_empty());
+    }
+
+    #[test]
+    fn test_jumps() {
+        assert_eq!(
+            messages(vec!["jnz 0 3", "mov a 1", "jnz a 0", "jnz 3 0"]),
+            vec![
+                "line 1: warning: condition is always zero, the jump is never taken",
+                "line 3: warning: jump to itself loops forever unless register a is zero",
+                "line 4: error: jump to itself loops forever",
+                "line 4: info: condition 3 is constant, the jump is always taken",
+            ]
+        );
+        assert_eq!(
+            messages(vec!["mov a 1", "jnz a 5"]),
+            vec!["line 2: error: jump by 5 leaves the program"]
+        );
+        assert_eq!(
+            messages(vec!["jz 3 1", "jz 0 1", "in a 0", "jz a 0", "jmp 0"]),
+            vec![
+                "line 1: warning: condition 3 is never zero, the jump is never taken",
+                "line 2: info: condition 0 is constant, the jump is always taken",
+                "line 4: warning: jump to itself loops forever unless register a is not zero",
+                "line 5: error: jump to itself loops forever",
+            ]
+        );
+        assert_eq!(
+            messages(vec!["mov a 1", "cmp a 1", "je 0", "jg -4"]),
+            vec![
+                "line 3: warning: jump to itself loops forever once taken",
+                "line 4: error: jump by -4 leaves the program",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        assert_eq!(
+            messages(vec!["jnz 1 2", "mov a 1"]),
+            vec![
+                "line 1: info: condition 1 is constant, the jump is always taken",
+                "line 2: warning: instructions up to line 2 are unreachable",
+                "line 2: warning: register a is written but never read",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unused_write() {
+        assert_eq!(
+            messages(vec!["mov a 1", "mov b 2", "print a"]),
+            vec!["line 2: warning: register b is written but never read"]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::{collections::HashSet, ops::Range};
+
+use super::cfg::{next_pcs, Cfg};
+use super::{reads, registers, writes};
+use crate::vm::parser::{Instruction, Register};
+
+/// Registers whose current value may still be read, before and after every pc.
+#[derive(Debug, PartialEq)]
+pub struct Liveness {
+    pub live_in: Vec<HashSet<Register>>,
+    pub live_out: Vec<HashSet<Register>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BlockPressure {
+    pub block: usize,
+    pub pcs: Range<usize>,
+    /// Largest number of registers live at once inside the block.
+    pub max_live: usize,
+    /// Registers live at the pc where the maximum is first reached.
+    pub registers: Vec<Register>,
+}
+
+pub fn liveness(instructions: &[Instruction]) -> Liveness {
+    let len = instructions.len();
+    let successors = (0..len)
+        .map(|pc| {
+            let next = next_pcs(instructions, pc);
+            // a computed jump may land anywhere
+            if next.contains(&None) {
+                (0..len).collect()
+            } else {
+                next.into_iter()
+                    .flatten()
+                    .filter(|pc| *pc < len)
+                    .collect::<Vec<_>>()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let program = registers(instructions);
+    let mut live_in = vec![HashSet::new(); len];
+    let mut live_out = vec![HashSet::new(); len];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for pc in (0..len).rev() {
+            let out = successors[pc]
+                .iter()
+                .flat_map(|next| live_in[*next].iter().cloned())
+                .collect::<HashSet<_>>();
+            let mut inn = out.clone();
+            if let Some(reg) = writes(&instructions[pc]) {
+                inn.remove(reg);
+            }
+            inn.extend(reads(&instructions[pc], &program).into_iter().cloned());
+            if inn != live_in[pc] || out != live_out[pc] {
+                live_in[pc] = inn;
+                live_out[pc] = out;
+                changed = true;
+            }
+        }
+    }
+    Liveness { live_in, live_out }
+}
+
+impl Liveness {
+    /// Pc ranges where each register is live on entry, sorted by register name.
+    pub fn live_ranges(&self) -> Vec<(Register, Vec<Range<usize>>)> {
+        let mut registers = self
+            .live_in
+            .iter()
+            .flatten()
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        registers.sort_by_key(|reg| reg.to_string());
+        registers
+            .into_iter()
+            .map(|reg| {
+                let mut ranges: Vec<Range<usize>> = Vec::new();
+                for pc in (0..self.live_in.len()).filter(|pc| self.live_in[*pc].contains(&reg)) {
+                    match ranges.last_mut() {
+                        Some(range) if range.end == pc => range.end = pc + 1,
+                        _ => ranges.push(pc..pc + 1),
+                    }
+                }
+                (reg, ranges)
+            })
+            .collect()
+    }
+
+    /// Register pressure of every basic block.
+    pub fn pressure(&self, cfg: &Cfg) -> Vec<BlockPressure> {
+        cfg.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| {
+                let pc = (block.start..block.end)
+                    .rev()
+                    .max_by_key(|pc| self.live_in[*pc].len())
+                    .unwrap();
+                let mut registers = self.live_in[pc].iter().cloned().collect::<Vec<_>>();
+                registers.sort_by_key(|reg| reg.to_string());
+                BlockPressure {
+                    block: i,
+                    pcs: block.start..block.end,
+                    max_live: registers.len(),
+                    registers,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+
+    fn names(set: &HashSet<Register>) -> Vec<String> {
+        let mut names = set.iter().map(|reg| reg.to_string()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_live_sets() {
+        let instructions = parse_instructions(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "mov c 1", "print c",
+        ])
+        .unwrap();
+        let liveness = liveness(&instructions);
+        assert_eq!(names(&liveness.live_in[0]), Vec::<String>::new());
+        assert_eq!(names(&liveness.live_in[2]), vec!["a", "b"]);
+        assert_eq!(names(&liveness.live_out[3]), vec!["a", "b"]);
+        assert_eq!(names(&liveness.live_in[5]), vec!["c"]);
+        assert_eq!(
+            liveness
+                .live_ranges()
+                .iter()
+                .map(|(reg, ranges)| format!("{reg}: {ranges:?}"))
+                .collect::<Vec<_>>(),
+            vec!["a: [1..4]", "b: [2..4]", "c: [5..6]",]
+        );
+    }
+
+    #[test]
+    fn test_host_reads() {
+        let instructions = parse_instructions(vec!["mov a 9", "syscall 1"]).unwrap();
+        assert_eq!(names(&liveness(&instructions).live_in[1]), vec!["a"]);
+    }
+
+    #[test]
+    fn test_pressure() {
+        let instructions = parse_instructions(vec![
+            "mov a 1", "mov b 2", "add a b", "jnz a 2", "print a", "print b",
+        ])
+        .unwrap();
+        let pressure = liveness(&instructions).pressure(&Cfg::build(&instructions));
+        assert_eq!(pressure.len(), 3);
+        assert_eq!(pressure[0].pcs, 0..4);
+        assert_eq!(pressure[0].max_live, 2);
+        assert_eq!(pressure[1].max_live, 2);
+        assert_eq!(pressure[2].max_live, 1);
+    }
+}
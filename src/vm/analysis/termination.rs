@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+
+use super::cfg::jump_target;
+use crate::vm::parser::{ConstOrReg, Constant, Flags, Instruction, Register};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Value {
+    Known(Constant),
+    /// Read from a device, so it can differ between runs.
+    Input,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// Every path stops, the longest one after `steps` instructions.
+    Halts {
+        steps: usize,
+    },
+    /// Executing `prefix` leads into `cycle`, which repeats the same state forever.
+    Loops {
+        prefix: Vec<usize>,
+        cycle: Vec<usize>,
+    },
+    Unknown(String),
+}
+
+#[derive(Clone)]
+struct Path {
+    pc: usize,
+    registers: HashMap<Register, Value>,
+    strings: HashMap<Register, String>,
+    carry: Value,
+    /// `None` once `cmp` compared a value read from input.
+    flags: Option<Flags>,
+    trace: Vec<usize>,
+    seen: HashMap<Key, usize>,
+}
+
+/// Everything the next steps of a path depend on, with registers sorted by name.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    pc: usize,
+    registers: Vec<(String, Value)>,
+    strings: Vec<(String, String)>,
+    carry: Value,
+    flags: Option<Flags>,
+}
+
+impl Key {
+    /// Whether no part of the state was read from input.
+    fn is_known(&self) -> bool {
+        self.registers
+            .iter()
+            .map(|(_, value)| value)
+            .chain([&self.carry])
+            .all(|value| matches!(value, Value::Known(_)))
+            && self.flags.is_some()
+    }
+}
+
+impl Path {
+    fn key(&self) -> Key {
+        let mut registers = self
+            .registers
+            .iter()
+            .map(|(reg, value)| (reg.to_string(), *value))
+            .collect::<Vec<_>>();
+        registers.sort();
+        let mut strings = self
+            .strings
+            .iter()
+            .map(|(reg, text)| (reg.to_string(), text.clone()))
+            .collect::<Vec<_>>();
+        strings.sort();
+        Key {
+            pc: self.pc,
+            registers,
+            strings,
+            carry: self.carry,
+            flags: self.flags,
+        }
+    }
+
+    fn load(&self, x: &ConstOrReg) -> Option<Value> {
+        match x {
+            ConstOrReg::Const(c) => Some(Value::Known(*c)),
+            ConstOrReg::Reg(reg) => self.registers.get(reg).copied(),
+        }
+    }
+
+    /// Target of a jump from the current pc by `offset`, `None` where the VM stops instead
+    /// and an error when the target depends on input.
+    fn target(&self, offset: &ConstOrReg, len: usize) -> Result<Option<usize>, String> {
+        match self.load(offset) {
+            // jumping outside of the program stops the VM
+            Some(Value::Known(offset)) => Ok(jump_target(self.pc, &ConstOrReg::Const(offset), len)),
+            Some(Value::Input) => Err(format!("jump on line {} depends on input", self.pc + 1)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores `op` applied to the values of `operands` in `x` and moves on, the result is
+    /// unknown as soon as one operand comes from input. Halts where the VM would trap: on an
+    /// uninitialised operand or when `op` returns `None`.
+    fn compute<const N: usize>(
+        &mut self,
+        x: &Register,
+        operands: [&Register; N],
+        op: impl FnOnce([Constant; N]) -> Option<Constant>,
+    ) -> Step {
+        let mut values = [Constant::ZERO; N];
+        let mut input = false;
+        for (value, reg) in values.iter_mut().zip(operands) {
+            match self.registers.get(reg) {
+                Some(Value::Known(known)) => *value = *known,
+                Some(Value::Input) => input = true,
+                None => return Step::Halt,
+            }
+        }
+        let result = if input {
+            Value::Input
+        } else {
+            match op(values) {
+                Some(result) => Value::Known(result),
+                None => return Step::Halt,
+            }
+        };
+        self.registers.insert(x.clone(), result);
+        self.pc += 1;
+        Step::Next
+    }
+}
+
+enum Step {
+    Next,
+    Fork(Path),
+    Halt,
+    Unknown(String),
+}
+
+fn step(path: &mut Path, instruction: &Instruction, len: usize) -> Step {
+    let pc = path.pc;
+    match instruction {
+        Instruction::Mov(x, y) => match path.load(y) {
+            Some(value) => {
+                path.registers.insert(x.clone(), value);
+            }
+            None => return Step::Halt,
+        },
+        Instruction::Add(x, y)
+        | Instruction::Sub(x, y)
+        | Instruction::AddC(x, y)
+        | Instruction::SubB(x, y) => {
+            let carry_in = match instruction {
+                Instruction::Add(..) | Instruction::Sub(..) => Value::Known(Constant::ZERO),
+                _ => path.carry,
+            };
+            let op = match instruction {
+                Instruction::Sub(..) | Instruction::SubB(..) => Constant::sub_with_borrow,
+                _ => Constant::add_with_carry,
+            };
+            let (value, carry) = match (path.registers.get(x), path.registers.get(y), carry_in) {
+                (Some(Value::Known(a)), Some(Value::Known(b)), Value::Known(c)) => {
+                    let (value, carry) = op(*a, *b, c != Constant::ZERO);
+                    (
+                        Value::Known(value),
+                        Value::Known(Constant::of(carry as i32)),
+                    )
+                }
+                (Some(_), Some(_), _) => (Value::Input, Value::Input),
+                _ => return Step::Halt,
+            };
+            path.registers.insert(x.clone(), value);
+            path.carry = carry;
+        }
+        Instruction::Mul(x, y) => {
+            return path.compute(x, [x, y], |[a, b]| Some(Constant::of(a.wrapping_mul(*b))))
+        }
+        Instruction::Div(x, y) => return path.compute(x, [x, y], |[a, b]| a.quotient(b)),
+        Instruction::Mod(x, y) => return path.compute(x, [x, y], |[a, b]| a.remainder(b)),
+        Instruction::MulH(x, y) => return path.compute(x, [x, y], |[a, b]| Some(a.mul_high(b))),
+        Instruction::FxMul(x, y) => return path.compute(x, [x, y], |[a, b]| Some(a.fx_mul(b))),
+        Instruction::FxDiv(x, y) => return path.compute(x, [x, y], |[a, b]| a.fx_div(b)),
+        Instruction::BExt(x, y, field) => {
+            return path.compute(x, [y], |[a]| Some(a.bit_extract(*field)))
+        }
+        Instruction::BIns(x, y, field) => {
+            return path.compute(x, [x, y], |[a, b]| Some(a.bit_insert(b, *field)))
+        }
+        Instruction::And(x, n)
+        | Instruction::Or(x, n)
+        | Instruction::Xor(x, n)
+        | Instruction::Shl(x, n)
+        | Instruction::Shr(x, n) => {
+            let op = |a: Constant, n| match instruction {
+                Instruction::And(..) => Some(a & n),
+                Instruction::Or(..) => Some(a | n),
+                Instruction::Xor(..) => Some(a ^ n),
+                Instruction::Shl(..) => Some(a.shift_left(n)),
+                _ => Some(a.shift_right(n)),
+            };
+            return match n {
+                ConstOrReg::Const(n) => path.compute(x, [x], |[a]| op(a, *n)),
+                ConstOrReg::Reg(n) => path.compute(x, [x, n], |[a, n]| op(a, n)),
+            };
+        }
+        Instruction::Not(x) => return path.compute(x, [x], |[a]| Some(!a)),
+        Instruction::Rol(x, n) | Instruction::Ror(x, n) => {
+            let rotate = |a: Constant, n| match instruction {
+                Instruction::Ror(..) => Some(a.rotate_right(n)),
+                _ => Some(a.rotate_left(n)),
+            };
+            return match n {
+                ConstOrReg::Const(n) => path.compute(x, [x], |[a]| rotate(a, *n)),
+                ConstOrReg::Reg(n) => path.compute(x, [x, n], |[a, n]| rotate(a, n)),
+            };
+        }
+        Instruction::Popcnt(x, y) => {
+            return path.compute(x, [y], |[a]| Some(Constant::of(a.count_ones() as i32)))
+        }
+        Instruction::Clz(x, y) => {
+            return path.compute(x, [y], |[a]| Some(Constant::of(a.leading_zeros() as i32)))
+        }
+        Instruction::Ctz(x, y) => {
+            return path.compute(x, [y], |[a]| Some(Constant::of(a.trailing_zeros() as i32)))
+        }
+        // print traps on values that aren't characters, out and emit pass anything on
+        Instruction::Print(x) => match path.registers.get(x) {
+            Some(Value::Known(value))
+                if u32::try_from(**value)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .is_none() =>
+            {
+                return Step::Halt
+            }
+            Some(_) => (),
+            None => return Step::Halt,
+        },
+        Instruction::Out(_, x) | Instruction::Emit(_, x) if !path.registers.contains_key(x) => {
+            return Step::Halt
+        }
+        Instruction::Out(_, _) | Instruction::Emit(_, _) => (),
+        Instruction::In(x, _) | Instruction::Poll(x, _) | Instruction::Read(x) => {
+            path.registers.insert(x.clone(), Value::Input);
+        }
+        Instruction::Syscall(n) => {
+            return Step::Unknown(format!("host function {n} called on line {}", pc + 1))
+        }
+        Instruction::Custom(name, _) => {
+            return Step::Unknown(format!("extension {name} called on line {}", pc + 1))
+        }
+        Instruction::SMov(x, text) => {
+            path.strings.insert(x.clone(), text.clone());
+        }
+        Instruction::SCat(x, y) => match (path.strings.get(x), path.strings.get(y)) {
+            (Some(a), Some(b)) => {
+                let text = format!("{a}{b}");
+                path.strings.insert(x.clone(), text);
+            }
+            _ => return Step::Halt,
+        },
+        Instruction::SLen(x, y) => match path.strings.get(y) {
+            Some(text) => {
+                let len = Value::Known(Constant::of(text.chars().count() as i32));
+                path.registers.insert(x.clone(), len);
+            }
+            None => return Step::Halt,
+        },
+        Instruction::SPrint(x) if !path.strings.contains_key(x) => return Step::Halt,
+        Instruction::SPrint(_) => (),
+        Instruction::Jnz(x, y) | Instruction::Jz(x, y) => {
+            let cond = match path.load(x) {
+                Some(cond) => cond,
+                None => return Step::Halt,
+            };
+            let on_zero = matches!(instruction, Instruction::Jz(..));
+            if let Value::Known(cond) = cond {
+                if (cond == Constant::ZERO) != on_zero {
+                    path.pc += 1;
+                    return Step::Next;
+                }
+            }
+            let target = match path.target(y, len) {
+                Ok(Some(target)) => target,
+                Ok(None) => return Step::Halt,
+                Err(reason) => return Step::Unknown(reason),
+            };
+            if let Value::Input = cond {
+                let mut zero = path.clone();
+                if let ConstOrReg::Reg(reg) = x {
+                    zero.registers
+                        .insert(reg.clone(), Value::Known(Constant::ZERO));
+                }
+                (zero.pc, path.pc) = if on_zero {
+                    (target, pc + 1)
+                } else {
+                    (pc + 1, target)
+                };
+                return Step::Fork(zero);
+            }
+            path.pc = target;
+            return Step::Next;
+        }
+        Instruction::Jmp(offset) => {
+            path.pc = match path.target(offset, len) {
+                Ok(Some(target)) => target,
+                Ok(None) => return Step::Halt,
+                Err(reason) => return Step::Unknown(reason),
+            };
+            return Step::Next;
+        }
+        Instruction::Cmp(x, y) => match (path.registers.get(x), path.load(y)) {
+            (Some(Value::Known(a)), Some(Value::Known(b))) => {
+                path.flags = Some(Flags::compare(*a, b));
+            }
+            (Some(_), Some(_)) => path.flags = None,
+            _ => return Step::Halt,
+        },
+        Instruction::JumpIf(condition, offset) => {
+            if path.flags.is_some_and(|flags| !condition.holds(flags)) {
+                path.pc += 1;
+                return Step::Next;
+            }
+            let target = match path.target(offset, len) {
+                Ok(Some(target)) => target,
+                Ok(None) => return Step::Halt,
+                Err(reason) => return Step::Unknown(reason),
+            };
+            if path.flags.is_none() {
+                let mut not_taken = path.clone();
+                not_taken.pc += 1;
+                path.pc = target;
+                return Step::Fork(not_taken);
+            }
+            path.pc = target;
+            return Step::Next;
+        }
+    }
+    path.pc += 1;
+    Step::Next
+}
+
+/// Explores every execution path for up to `max_steps` instructions each, giving up after
+/// `max_paths` paths. Values read from devices are unknown and branch both ways.
+pub fn check_termination(
+    instructions: &[Instruction],
+    max_steps: usize,
+    max_paths: usize,
+) -> Termination {
+    let mut pending = vec![Path {
+        pc: 0,
+        registers: HashMap::new(),
+        strings: HashMap::new(),
+        carry: Value::Known(Constant::ZERO),
+        flags: Some(Flags::default()),
+        trace: Vec::new(),
+        seen: HashMap::new(),
+    }];
+    let mut explored = 0;
+    let mut longest = 0;
+    let mut input_cycle = None;
+
+    while let Some(mut path) = pending.pop() {
+        explored += 1;
+        if explored > max_paths {
+            return Termination::Unknown(format!("more than {max_paths} paths to explore"));
+        }
+        loop {
+            let Some(instruction) = instructions.get(path.pc) else {
+                longest = longest.max(path.trace.len());
+                break;
+            };
+            let key = path.key();
+            if let Some(&start) = path.seen.get(&key) {
+                let cycle = path.trace[start..].to_vec();
+                if key.is_known() {
+                    let prefix = path.trace[..start].to_vec();
+                    return Termination::Loops { prefix, cycle };
+                }
+                input_cycle.get_or_insert(cycle);
+                break;
+            }
+            if path.trace.len() >= max_steps {
+                return Termination::Unknown(format!("a path runs longer than {max_steps} steps"));
+            }
+            path.seen.insert(key, path.trace.len());
+            path.trace.push(path.pc);
+            match step(&mut path, instruction, instructions.len()) {
+                Step::Next => (),
+                Step::Fork(other) => pending.push(other),
+                Step::Halt => {
+                    longest = longest.max(path.trace.len());
+                    break;
+                }
+                Step::Unknown(reason) => return Termination::Unknown(reason),
+            }
+        }
+    }
+    match input_cycle {
+        Some(cycle) => Termination::Unknown(format!(
+            "may loop through lines {:?} depending on input",
+            cycle.iter().map(|pc| pc + 1).collect::<Vec<_>>()
+        )),
+        None => Termination::Halts { steps: longest },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+
+    fn check(input: Vec<&str>) -> Termination {
+        check_termination(&parse_instructions(input).unwrap(), 1000, 100)
+    }
+
+    #[test]
+    fn test_halts() {
+        assert_eq!(
+            check(vec!["mov a 2", "mov b -1", "add a b", "jnz a -1"]),
+            Termination::Halts { steps: 6 }
+        );
+        assert_eq!(
+            check(vec!["in a 0", "jnz a 2", "print a"]),
+            Termination::Halts { steps: 3 }
+        );
+        assert_eq!(
+            check(vec!["print a", "jnz 1 0"]),
+            Termination::Halts { steps: 1 }
+        );
+        assert_eq!(
+            check(vec!["mov a -1", "print a", "jnz 1 -1"]),
+            Termination::Halts { steps: 2 }
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_eq!(
+            check(vec!["mov a 1", "mov b 1", "add a b", "jnz 1 -1"]),
+            Termination::Unknown("a path runs longer than 1000 steps".to_string())
+        );
+        assert_eq!(
+            check(vec!["mov a 0", "jz a 2", "jmp 1", "jmp -1"]),
+            Termination::Loops {
+                prefix: vec![0, 1],
+                cycle: vec![3, 2],
+            }
+        );
+        assert_eq!(
+            check(vec!["mov a 1", "jnz a 2", "mov a 0", "jnz a 0"]),
+            Termination::Loops {
+                prefix: vec![0, 1],
+                cycle: vec![3],
+            }
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(
+            check(vec!["mov a 3", "cmp a 3", "jne 0", "cmp a 4", "jl 0"]),
+            Termination::Loops {
+                prefix: vec![0, 1, 2, 3],
+                cycle: vec![4],
+            }
+        );
+        assert_eq!(
+            check(vec!["mov a 0", "mov b 1", "add a b", "cmp a 3", "jl -2"]),
+            Termination::Halts { steps: 11 }
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            check(vec![r#"smov s "ab""#, "slen n s", "jnz n 0"]),
+            Termination::Loops {
+                prefix: vec![0, 1],
+                cycle: vec![2],
+            }
+        );
+        assert_eq!(
+            check(vec![
+                r#"smov s "a""#,
+                r#"smov t "b""#,
+                "scat t s",
+                "jnz 1 -1"
+            ]),
+            Termination::Unknown("a path runs longer than 1000 steps".to_string())
+        );
+        assert_eq!(
+            check(vec!["sprint s", "jnz 1 0"]),
+            Termination::Halts { steps: 1 }
+        );
+    }
+
+    #[test]
+    fn test_input_dependent() {
+        assert_eq!(
+            check(vec!["in a 0", "jnz a 0"]),
+            Termination::Unknown("may loop through lines [2] depending on input".to_string())
+        );
+        assert_eq!(
+            check(vec!["in a 0", "jnz 1 a"]),
+            Termination::Unknown("jump on line 2 depends on input".to_string())
+        );
+        assert_eq!(
+            check(vec!["in a 0", "cmp a 3", "jl 0"]),
+            Termination::Unknown("may loop through lines [3] depending on input".to_string())
+        );
+        assert_eq!(
+            check(vec!["mov a 1", "syscall 4"]),
+            Termination::Unknown("host function 4 called on line 2".to_string())
+        );
+    }
+}
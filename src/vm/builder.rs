@@ -0,0 +1,142 @@
+use std::{io::Write, sync::Arc};
+
+use super::capabilities::Capabilities;
+use super::device::{Device, Random};
+use super::input::InputSource;
+use super::parser::{Constant, Register};
+use super::Vm;
+
+/// Configures a [`Vm`] before it runs:
+///
+///   let vm = Vm::builder().register("n", 10).budget(1_000).build();
+#[derive(Default)]
+pub struct VmBuilder {
+    registers: Vec<(Register, Constant)>,
+    budget: Option<u64>,
+    output: Option<Box<dyn Write + Send>>,
+    input: Option<Box<dyn InputSource>>,
+    capabilities: Capabilities,
+    devices: Vec<(Constant, Box<dyn Device>)>,
+    detect_cycles: Option<usize>,
+    history: usize,
+}
+
+impl VmBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts the program with `value` in `register`.
+    pub fn register(mut self, register: &str, value: i32) -> Self {
+        self.registers
+            .push((Register::of(register.to_string()), Constant::of(value)));
+        self
+    }
+
+    /// Stops the program once it has executed `steps` instructions, see
+    /// [`super::StepOutcome::BudgetExhausted`].
+    pub fn budget(mut self, steps: u64) -> Self {
+        self.budget = Some(steps);
+        self
+    }
+
+    pub fn output(mut self, output: Box<dyn Write + Send>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn input(mut self, input: Box<dyn InputSource>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn device(mut self, port: Constant, device: Box<dyn Device>) -> Self {
+        self.devices.push((port, device));
+        self
+    }
+
+    /// Attaches a random generator seeded with `seed` on port 2, so the run can be repeated.
+    pub fn seed(self, seed: u32) -> Self {
+        self.device(Constant::of(2), Box::new(Random::with_seed(seed)))
+    }
+
+    /// See [`Vm::detect_cycles`].
+    pub fn detect_cycles(mut self, every: usize) -> Self {
+        self.detect_cycles = Some(every);
+        self
+    }
+
+    /// See [`Vm::set_history`].
+    pub fn history(mut self, len: usize) -> Self {
+        self.history = len;
+        self
+    }
+
+    pub fn build(self) -> Vm {
+        let mut vm = Vm::new();
+        vm.registers = Arc::new(self.registers.into_iter().collect());
+        vm.budget = self.budget;
+        if let Some(output) = self.output {
+            vm.set_output(output);
+        }
+        if let Some(input) = self.input {
+            vm.set_input(input);
+        }
+        vm.set_capabilities(self.capabilities);
+        for (port, device) in self.devices {
+            vm.attach_device(port, device);
+        }
+        if let Some(every) = self.detect_cycles {
+            vm.detect_cycles(every);
+        }
+        vm.set_history(self.history);
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_program;
+    use crate::vm::{ExitStatus, StepOutcome};
+
+    #[test]
+    fn test_builder() {
+        let instructions = parse_program(vec!["read b", "add a b", "jnz 1 -1"]).unwrap();
+        let mut vm = Vm::builder()
+            .register("a", 10)
+            .input(Box::new(&b"\x05"[..]))
+            .budget(5)
+            .build();
+        assert_eq!(vm.interpret(&instructions), Ok(ExitStatus::BudgetExhausted));
+        assert_eq!(vm.pc, 1);
+        assert_eq!(
+            vm.registers[&Register::of("a".to_string())],
+            Constant::of(20)
+        );
+        assert_eq!(vm.step(&instructions), Ok(StepOutcome::BudgetExhausted));
+    }
+
+    #[test]
+    fn test_seed() {
+        let instructions = parse_program(vec!["in a 2", "in b 2"]).unwrap();
+        let draws = || {
+            let mut vm = Vm::builder()
+                .capabilities(Capabilities::all())
+                .seed(7)
+                .build();
+            vm.interpret(&instructions).unwrap();
+            (
+                vm.get(&Register::of("a".to_string())),
+                vm.get(&Register::of("b".to_string())),
+            )
+        };
+        let mut random = Random::with_seed(7);
+        assert_eq!(draws(), (Some(random.read()), Some(random.read())));
+    }
+}
@@ -0,0 +1,97 @@
+use std::{collections::BTreeSet, fmt::Display, str::FromStr};
+
+/// Group of host services behind a device, which a program may only use once granted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Capability {
+    /// Reading stdin and writing stdout through the console device.
+    Console,
+    /// Reading the clock.
+    Time,
+    /// Drawing random numbers.
+    Random,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 3] = [Capability::Console, Capability::Time, Capability::Random];
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Capability::Console => write!(f, "console"),
+            Capability::Time => write!(f, "time"),
+            Capability::Random => write!(f, "random"),
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Capability::ALL
+            .into_iter()
+            .find(|capability| capability.to_string() == s)
+            .ok_or_else(|| format!("unknown capability {s}, expected console, time or random"))
+    }
+}
+
+/// Capabilities granted to a program, nothing is granted by default.
+///
+/// Devices that don't belong to a capability, like ones an embedder attaches for its own
+/// purposes, are always available: attaching them is already an explicit opt-in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    granted: BTreeSet<Capability>,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capability::ALL.into_iter().collect()
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.granted.contains(&capability)
+    }
+}
+
+impl FromIterator<Capability> for Capabilities {
+    fn from_iter<T: IntoIterator<Item = Capability>>(iter: T) -> Self {
+        Capabilities {
+            granted: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl FromStr for Capabilities {
+    type Err = String;
+    /// Comma separated capability names, an empty string grants nothing.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_capabilities() {
+        let capabilities = "console,random".parse::<Capabilities>().unwrap();
+        assert!(capabilities.allows(Capability::Console));
+        assert!(!capabilities.allows(Capability::Time));
+        assert_eq!(
+            capabilities,
+            [Capability::Random, Capability::Console]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!("".parse::<Capabilities>(), Ok(Capabilities::default()));
+        assert_eq!(
+            "console,net".parse::<Capabilities>(),
+            Err("unknown capability net, expected console, time or random".to_string())
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt::Write;
+
+// Instruction hit counts are indexed by pc, and every source line holds exactly one
+// instruction, so pc N is reported as line N + 1.
+
+/// Renders hit counts as an lcov tracefile understood by genhtml and CI coverage tools.
+pub fn to_lcov(source_file: &str, hits: &[u64]) -> String {
+    let mut lcov = format!("TN:\nSF:{source_file}\n");
+    for (pc, count) in hits.iter().enumerate() {
+        writeln!(lcov, "DA:{},{count}", pc + 1).unwrap();
+    }
+    let covered = hits.iter().filter(|count| **count > 0).count();
+    writeln!(lcov, "LF:{}\nLH:{covered}\nend_of_record", hits.len()).unwrap();
+    lcov
+}
+
+pub fn summary(hits: &[u64]) -> String {
+    let covered = hits.iter().filter(|count| **count > 0).count();
+    let percent = if hits.is_empty() {
+        100.0
+    } else {
+        covered as f64 * 100.0 / hits.len() as f64
+    };
+    format!(
+        "covered {covered} of {} instructions ({percent:.1}%)",
+        hits.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_program;
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_lcov_from_run() {
+        let instructions = parse_program(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "jnz 1 2", "print a",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.enable_coverage();
+        vm.interpret(&instructions).unwrap();
+        let hits = vm.coverage().unwrap();
+        assert_eq!(hits, &[1, 1, 2, 2, 1, 0]);
+        assert_eq!(
+            to_lcov("prog.svm", hits),
+            "TN:\nSF:prog.svm\nDA:1,1\nDA:2,1\nDA:3,2\nDA:4,2\nDA:5,1\nDA:6,0\nLF:6\nLH:5\nend_of_record\n"
+        );
+        assert_eq!(summary(hits), "covered 5 of 6 instructions (83.3%)");
+    }
+}
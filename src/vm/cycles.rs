@@ -0,0 +1,55 @@
+use super::state::State;
+
+/// Notices a VM coming back to an earlier state, after which it can only loop forever unless
+/// a device answers differently. Samples are taken every `every` steps and compared with a
+/// saved one that is replaced after windows of doubling length (Brent's algorithm), so
+/// memory stays constant and a cycle is found within a few of its lengths once entered.
+pub(crate) struct CycleDetector {
+    every: usize,
+    steps: usize,
+    saved: Option<State>,
+    /// Samples taken since `saved`.
+    since: usize,
+    window: usize,
+}
+
+impl CycleDetector {
+    pub(crate) fn new(every: usize) -> Self {
+        CycleDetector {
+            every: every.max(1),
+            steps: 0,
+            saved: None,
+            since: 0,
+            window: 1,
+        }
+    }
+
+    /// Forgets the saved state, a device may answer differently the next time around.
+    pub(crate) fn reset(&mut self) {
+        self.steps = 0;
+        self.saved = None;
+        self.since = 0;
+        self.window = 1;
+    }
+
+    /// Counts one executed step, returns the repeated state and how many steps ago it was
+    /// seen once `state` matches the saved sample.
+    pub(crate) fn step(&mut self, state: impl FnOnce() -> State) -> Option<(State, usize)> {
+        self.steps += 1;
+        if self.steps < self.every {
+            return None;
+        }
+        self.steps = 0;
+        let state = state();
+        self.since += 1;
+        if self.saved.as_ref() == Some(&state) {
+            return Some((state, self.since * self.every));
+        }
+        if self.saved.is_none() || self.since >= self.window {
+            self.saved = Some(state);
+            self.since = 0;
+            self.window *= 2;
+        }
+        None
+    }
+}
@@ -0,0 +1,361 @@
+use std::{collections::BTreeSet, fmt::Display, io::Write};
+
+use super::error::VmError;
+use super::parser::{Constant, Instruction, Register};
+use super::{StepOutcome, Vm};
+
+// Runs a debugging session from a script instead of a terminal, one command per line:
+//
+//   break 4          # stop before line 4 runs
+//   continue         # run until a breakpoint or the end of the program
+//   run to 7         # run until line 7 is next
+//   step 100         # execute up to 100 instructions
+//   regs             # dump every register
+//   print a          # dump a single register
+//   set a = 42       # overwrite a register before resuming
+//   delete 4         # remove the breakpoint on line 4
+//   break on jnz     # stop before any jnz, `io` stands for in, out and poll
+//   delete on jnz
+//
+// Lines are 1-based like in VM error messages and `#` starts a comment.
+
+#[derive(Debug, PartialEq)]
+pub struct ScriptError {
+    /// Line of the script with the failing command.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "script line {}: {}", self.line, self.message)
+    }
+}
+
+pub struct Debugger<'a> {
+    vm: &'a mut Vm,
+    instructions: &'a [Instruction],
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: BTreeSet<&'static str>,
+    halted: bool,
+    /// Why the program stopped early, if it did.
+    error: Option<VmError>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(vm: &'a mut Vm, instructions: &'a [Instruction]) -> Self {
+        vm.start(instructions, 0);
+        Debugger {
+            vm,
+            instructions,
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: BTreeSet::new(),
+            halted: instructions.is_empty(),
+            error: None,
+        }
+    }
+
+    fn step(&mut self) -> bool {
+        if !self.halted {
+            match self.vm.step(self.instructions) {
+                Ok(outcome) => {
+                    self.halted =
+                        outcome != StepOutcome::Continued || self.vm.pc >= self.instructions.len();
+                }
+                Err(err) => {
+                    self.halted = true;
+                    self.error = Some(err);
+                }
+            }
+        }
+        !self.halted
+    }
+
+    /// Runs until `stop` holds for the next pc, always executing at least one instruction
+    /// so that continuing from a breakpoint makes progress.
+    fn run_until(&mut self, stop: impl Fn(&Self, usize) -> bool) {
+        while self.step() && !stop(self, self.vm.pc) {}
+    }
+
+    fn is_breakpoint(&self, pc: usize) -> bool {
+        self.breakpoints.contains(&pc)
+            || self
+                .instructions
+                .get(pc)
+                .is_some_and(|instruction| self.opcode_breakpoints.contains(instruction.opcode()))
+    }
+
+    fn location(&self) -> String {
+        if let Some(err) = &self.error {
+            return format!("program trapped: {err}");
+        }
+        match self.instructions.get(self.vm.pc) {
+            Some(instruction) if !self.halted => {
+                format!("stopped before line {}: {instruction}", self.vm.pc + 1)
+            }
+            _ => "program ended".to_string(),
+        }
+    }
+
+    fn opcodes(class: &str) -> Result<&'static [&'static str], String> {
+        const OPCODES: &[&str] = &Instruction::OPCODES;
+        match class {
+            "io" => Ok(&["in", "out", "poll"]),
+            _ => OPCODES
+                .iter()
+                .position(|opcode| *opcode == class)
+                .map(|i| &OPCODES[i..=i])
+                .ok_or_else(|| format!("unknown instruction class {class}")),
+        }
+    }
+
+    fn line(&self, arg: &str) -> Result<usize, String> {
+        let line = arg
+            .parse::<usize>()
+            .map_err(|_| format!("expected a line number, found {arg}"))?;
+        if line == 0 || line > self.instructions.len() {
+            return Err(format!(
+                "line {line} is outside of the program (1..={})",
+                self.instructions.len()
+            ));
+        }
+        Ok(line - 1)
+    }
+
+    fn command(&mut self, words: &[&str], out: &mut impl Write) -> Result<(), String> {
+        let io = |err: std::io::Error| err.to_string();
+        match words {
+            ["break", "on", class] => {
+                self.opcode_breakpoints.extend(Self::opcodes(class)?);
+            }
+            ["delete", "on", class] => {
+                for opcode in Self::opcodes(class)? {
+                    if !self.opcode_breakpoints.remove(opcode) {
+                        return Err(format!("no breakpoint on {opcode}"));
+                    }
+                }
+            }
+            ["break", line] => {
+                let pc = self.line(line)?;
+                self.breakpoints.insert(pc);
+            }
+            ["delete", line] => {
+                let pc = self.line(line)?;
+                if !self.breakpoints.remove(&pc) {
+                    return Err(format!("no breakpoint on line {line}"));
+                }
+            }
+            ["continue"] => {
+                self.run_until(|debugger, pc| debugger.is_breakpoint(pc));
+                writeln!(out, "{}", self.location()).map_err(io)?;
+            }
+            ["run", "to", line] => {
+                let target = self.line(line)?;
+                self.run_until(|debugger, pc| pc == target || debugger.is_breakpoint(pc));
+                writeln!(out, "{}", self.location()).map_err(io)?;
+            }
+            ["step", rest @ ..] if rest.len() <= 1 => {
+                let count = match rest.first() {
+                    Some(count) => count
+                        .parse::<usize>()
+                        .map_err(|_| format!("expected a step count, found {count}"))?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if !self.step() {
+                        break;
+                    }
+                }
+                writeln!(out, "{}", self.location()).map_err(io)?;
+            }
+            ["regs"] => {
+                let mut registers = self.vm.registers.iter().collect::<Vec<_>>();
+                registers.sort_by_key(|(reg, _)| reg.to_string());
+                for (reg, value) in registers {
+                    writeln!(out, "{reg} = {value}").map_err(io)?;
+                }
+                let mut strings = self.vm.strings.iter().collect::<Vec<_>>();
+                strings.sort_by_key(|(reg, _)| reg.to_string());
+                for (reg, text) in strings {
+                    writeln!(out, "{reg} = {text:?}").map_err(io)?;
+                }
+                if self.vm.carry {
+                    writeln!(out, "carry is set").map_err(io)?;
+                }
+                if self.vm.flags.zero {
+                    writeln!(out, "zero is set").map_err(io)?;
+                }
+                if self.vm.flags.negative {
+                    writeln!(out, "negative is set").map_err(io)?;
+                }
+            }
+            ["print", reg] => {
+                let reg = reg.parse::<Register>().map_err(|err| err.to_string())?;
+                match (self.vm.registers.get(&reg), self.vm.strings.get(&reg)) {
+                    (Some(value), _) => writeln!(out, "{reg} = {value}"),
+                    (None, Some(text)) => writeln!(out, "{reg} = {text:?}"),
+                    (None, None) => writeln!(out, "{reg} is not initialized"),
+                }
+                .map_err(io)?;
+            }
+            ["set", reg, "=", value] => {
+                let reg = reg.parse::<Register>().map_err(|err| err.to_string())?;
+                let value = value
+                    .parse::<Constant>()
+                    .map_err(|_| format!("expected a number, found {value}"))?;
+                self.vm.set_register(&reg, value);
+            }
+            _ => return Err(format!("unknown command `{}`", words.join(" "))),
+        }
+        Ok(())
+    }
+
+    /// Executes every command of `script`, writing what the commands report to `out`.
+    pub fn run_script(&mut self, script: &str, out: &mut impl Write) -> Result<(), ScriptError> {
+        for (i, line) in script.lines().enumerate() {
+            let line_text = line.split('#').next().unwrap_or_default();
+            let words = line_text.split_whitespace().collect::<Vec<_>>();
+            if words.is_empty() {
+                continue;
+            }
+            self.command(&words, out).map_err(|message| ScriptError {
+                line: i + 1,
+                message,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+
+    fn run(program: Vec<&str>, script: &str) -> Result<String, ScriptError> {
+        let instructions = parse_instructions(program).unwrap();
+        let mut vm = Vm::new();
+        let mut out = Vec::new();
+        Debugger::new(&mut vm, &instructions).run_script(script, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_breakpoints_and_dumps() {
+        let out = run(
+            vec!["mov a 2", "mov b -1", "add a b", "jnz a -1", "mov c 7"],
+            "break 3  # the loop body\ncontinue\nregs\ncontinue\nprint a\ndelete 3\ncontinue\nprint c",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "stopped before line 3: add a b\n\
+             a = 2\nb = -1\n\
+             stopped before line 3: add a b\n\
+             a = 1\n\
+             program ended\n\
+             c = 7\n"
+        );
+    }
+
+    #[test]
+    fn test_step_and_run_to() {
+        let out = run(
+            vec!["mov a 2", "mov b -1", "add a b", "jnz a -1", "mov c 7"],
+            "step 2\nprint a\nrun to 5\nprint a\nprint c\nstep 100",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "stopped before line 3: add a b\n\
+             a = 2\n\
+             stopped before line 5: mov c 7\n\
+             a = 0\n\
+             c is not initialized\n\
+             program ended\n"
+        );
+    }
+
+    #[test]
+    fn test_set_register() {
+        let out = run(
+            vec!["mov a 2", "mov b -1", "add a b", "jnz a -1", "mov c a"],
+            "run to 3\nset a = 10\nstep 2\nprint a\nset a = 1\ncontinue\nregs",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "stopped before line 3: add a b\n\
+             stopped before line 3: add a b\n\
+             a = 9\n\
+             program ended\n\
+             a = 0\nb = -1\nc = 0\ncarry is set\n"
+        );
+        assert_eq!(
+            run(vec!["mov a 1"], "set a = one").unwrap_err().message,
+            "expected a number, found one"
+        );
+    }
+
+    #[test]
+    fn test_opcode_breakpoints() {
+        let out = run(
+            vec!["mov a 2", "mov b -1", "add a b", "jnz a -1", "mov c 7"],
+            "break on jnz\ncontinue\ncontinue\ndelete on jnz\ncontinue",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "stopped before line 4: jnz a -1\n\
+             stopped before line 4: jnz a -1\n\
+             program ended\n"
+        );
+        let out = run(vec!["mov a 1", "out 0 a"], "break on io\ncontinue").unwrap();
+        assert_eq!(out, "stopped before line 2: out 0 a\n");
+    }
+
+    #[test]
+    fn test_trap() {
+        let out = run(
+            vec!["mov a 1", "add a b", "mov c 1"],
+            "continue\nregs\nstep",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "program trapped: Register b must be initialized on line: 2\n\
+             a = 1\n\
+             program trapped: Register b must be initialized on line: 2\n"
+        );
+    }
+
+    #[test]
+    fn test_string_registers() {
+        let out = run(
+            vec![r#"smov s "hi\n""#, "slen n s"],
+            "step 2\nregs\nprint s",
+        )
+        .unwrap();
+        assert_eq!(out, "program ended\nn = 3\ns = \"hi\\n\"\ns = \"hi\\n\"\n");
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let program = vec!["mov a 1"];
+        assert_eq!(
+            run(program.clone(), "regs\nbreak 9"),
+            Err(ScriptError {
+                line: 2,
+                message: "line 9 is outside of the program (1..=1)".to_string()
+            })
+        );
+        assert_eq!(
+            run(program, "\nfly away").unwrap_err().to_string(),
+            "script line 2: unknown command `fly away`"
+        );
+        assert_eq!(
+            run(vec!["mov a 1"], "break on fly").unwrap_err().message,
+            "unknown instruction class fly"
+        );
+    }
+}
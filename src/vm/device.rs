@@ -0,0 +1,267 @@
+use std::{
+    io::{Read, Write},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::capabilities::Capability;
+use super::parser::Constant;
+
+/// Peripheral attached to an I/O port, accessed with `in r port` / `out port r`.
+pub trait Device: Send {
+    fn read(&mut self) -> Constant;
+
+    /// Fails with a message when the device can't take `value`, which traps the program.
+    fn write(&mut self, value: Constant) -> Result<(), String>;
+
+    /// Whether `read` would return without blocking, checked by `poll r port`.
+    fn poll(&mut self) -> bool {
+        true
+    }
+
+    /// Capability a program needs to be granted to use the device, none by default.
+    fn capability(&self) -> Option<Capability> {
+        None
+    }
+}
+
+/// Reads bytes from stdin (-1 on end of input) and writes characters to stdout.
+///
+/// Stdin is consumed by a background thread started on first use, so polling never blocks.
+pub struct Console {
+    input: Option<Receiver<u8>>,
+    pending: Option<u8>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Console {
+            input: None,
+            pending: None,
+        }
+    }
+
+    fn input(&mut self) -> &Receiver<u8> {
+        self.input.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for byte in std::io::stdin().lock().bytes() {
+                    match byte {
+                        Ok(byte) if sender.send(byte).is_ok() => (),
+                        _ => return,
+                    }
+                }
+            });
+            receiver
+        })
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self) -> Constant {
+        if let Some(byte) = self.pending.take() {
+            return Constant::of(byte as i32);
+        }
+        match self.input().recv() {
+            Ok(byte) => Constant::of(byte as i32),
+            Err(_) => Constant::of(-1),
+        }
+    }
+
+    fn write(&mut self, value: Constant) -> Result<(), String> {
+        let ch = u32::try_from(*value)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("value {value} is not a character"))?;
+        let mut stdout = std::io::stdout();
+        write!(stdout, "{ch}")
+            .and_then(|()| stdout.flush())
+            .map_err(|err| err.to_string())
+    }
+
+    fn poll(&mut self) -> bool {
+        if self.pending.is_some() {
+            return true;
+        }
+        match self.input().try_recv() {
+            Ok(byte) => {
+                self.pending = Some(byte);
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            // end of input, reading returns -1 right away
+            Err(TryRecvError::Disconnected) => true,
+        }
+    }
+
+    fn capability(&self) -> Option<Capability> {
+        Some(Capability::Console)
+    }
+}
+
+/// Where a [`Timer`] takes its readings from.
+pub enum Clock {
+    /// Wall clock milliseconds.
+    Real,
+    /// The same reading every time.
+    Fixed(i32),
+    /// Readings handed out in order, the last one repeats once they run out.
+    Sequence(Vec<i32>),
+}
+
+/// Milliseconds elapsed since the timer was created or last reset by a write.
+///
+/// Only the real clock is reset by writes, other clocks are fully under the host's control
+/// so that timing dependent programs can be run deterministically.
+pub struct Timer {
+    clock: Clock,
+    start: Instant,
+    next: usize,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer::with_clock(Clock::Real)
+    }
+
+    pub fn with_clock(clock: Clock) -> Self {
+        Timer {
+            clock,
+            start: Instant::now(),
+            next: 0,
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self) -> Constant {
+        let millis = match &self.clock {
+            Clock::Real => self.start.elapsed().as_millis() as i32,
+            Clock::Fixed(millis) => *millis,
+            Clock::Sequence(readings) => {
+                let reading = readings
+                    .get(self.next.min(readings.len().saturating_sub(1)))
+                    .copied();
+                self.next += 1;
+                reading.unwrap_or(0)
+            }
+        };
+        Constant::of(millis)
+    }
+
+    fn write(&mut self, _value: Constant) -> Result<(), String> {
+        self.start = Instant::now();
+        Ok(())
+    }
+
+    fn capability(&self) -> Option<Capability> {
+        Some(Capability::Time)
+    }
+}
+
+/// Xorshift pseudo random generator, writing a value reseeds it.
+pub struct Random {
+    seed: u32,
+    state: u32,
+}
+
+impl Random {
+    pub fn with_seed(seed: u32) -> Self {
+        Random {
+            seed,
+            // xorshift gets stuck on zero
+            state: seed.max(1),
+        }
+    }
+
+    /// Seed of the sequence being drawn, passing it to [`Random::with_seed`] repeats the run.
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(1);
+        Random::with_seed(nanos)
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Random {
+    fn read(&mut self) -> Constant {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        // keep values non-negative so they can be printed
+        Constant::of((x >> 1) as i32)
+    }
+
+    fn write(&mut self, value: Constant) -> Result<(), String> {
+        *self = Random::with_seed(*value as u32);
+        Ok(())
+    }
+
+    fn capability(&self) -> Option<Capability> {
+        Some(Capability::Random)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clocks() {
+        let mut fixed = Timer::with_clock(Clock::Fixed(7));
+        fixed.write(Constant::ZERO).unwrap();
+        assert_eq!([fixed.read(), fixed.read()], [7, 7].map(Constant::of));
+
+        let mut sequence = Timer::with_clock(Clock::Sequence(vec![1, 5]));
+        let readings = [(); 3].map(|_| sequence.read());
+        assert_eq!(readings, [1, 5, 5].map(Constant::of));
+    }
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let mut first = Random::with_seed(42);
+        let mut second = Random::with_seed(42);
+        for _ in 0..10 {
+            let value = first.read();
+            assert_eq!(value, second.read());
+            assert!(*value >= 0);
+        }
+    }
+
+    #[test]
+    fn test_random_reseed() {
+        let mut random = Random::with_seed(1);
+        let value = random.read();
+        random.read();
+        random.write(Constant::of(1)).unwrap();
+        assert_eq!(random.read(), value);
+        random.write(Constant::of(-1)).unwrap();
+        assert_eq!(random.seed(), u32::MAX);
+    }
+}
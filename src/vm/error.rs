@@ -0,0 +1,208 @@
+use std::fmt::Display;
+
+use super::capabilities::Capability;
+use super::parser::{Constant, Instruction, Register};
+use super::state::State;
+
+/// Runtime error that stops a program, with the state of the VM at that point. The VM stays
+/// on the failing instruction, see [`super::Vm::describe`] for a report with the surrounding
+/// program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VmError {
+    pub kind: ErrorKind,
+    pub context: Box<ErrorContext>,
+}
+
+/// Where a runtime error happened.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorContext {
+    pub pc: usize,
+    pub instruction: Instruction,
+    /// Registers when the instruction failed.
+    pub state: State,
+    /// Latest executed instructions before the failing one with their pcs, oldest first,
+    /// see [`super::Vm::set_history`].
+    pub history: Vec<(usize, Instruction)>,
+}
+
+impl VmError {
+    /// 1-based line of the failing instruction.
+    pub fn line(&self) -> usize {
+        self.kind.line()
+    }
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// What went wrong, `line` is the 1-based line of the failing instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    Uninitialized {
+        register: Register,
+        line: usize,
+    },
+    BothUninitialized {
+        x: Register,
+        y: Register,
+        line: usize,
+    },
+    UninitializedString {
+        register: Register,
+        line: usize,
+    },
+    DivisionByZero {
+        line: usize,
+    },
+    /// `print` of a value that isn't a character.
+    Unprintable {
+        value: Constant,
+        line: usize,
+    },
+    /// Writing to the VM output failed.
+    Output {
+        message: String,
+        line: usize,
+    },
+    /// Reading from the VM input failed.
+    Input {
+        message: String,
+        line: usize,
+    },
+    StringTooLong {
+        register: Register,
+        line: usize,
+    },
+    NoDevice {
+        port: Constant,
+        line: usize,
+    },
+    NoHostFn {
+        n: Constant,
+        line: usize,
+    },
+    NoExtension {
+        name: String,
+        line: usize,
+    },
+    /// An extension instruction failed with `message`.
+    Extension {
+        name: String,
+        message: String,
+        line: usize,
+    },
+    /// A host function called by `syscall n` failed with `message`.
+    HostFn {
+        n: Constant,
+        message: String,
+        line: usize,
+    },
+    NotAllowed {
+        capability: Capability,
+        port: Constant,
+        line: usize,
+    },
+    /// A jump by `offset` would leave the program.
+    JumpOutOfRange {
+        offset: Constant,
+        line: usize,
+    },
+    /// The VM came back to `state` after `steps` steps without device I/O in between.
+    Cycle {
+        state: Box<State>,
+        steps: usize,
+        line: usize,
+    },
+}
+
+impl ErrorKind {
+    pub fn line(&self) -> usize {
+        match self {
+            ErrorKind::Uninitialized { line, .. }
+            | ErrorKind::BothUninitialized { line, .. }
+            | ErrorKind::UninitializedString { line, .. }
+            | ErrorKind::DivisionByZero { line }
+            | ErrorKind::Unprintable { line, .. }
+            | ErrorKind::Output { line, .. }
+            | ErrorKind::Input { line, .. }
+            | ErrorKind::StringTooLong { line, .. }
+            | ErrorKind::NoDevice { line, .. }
+            | ErrorKind::NoHostFn { line, .. }
+            | ErrorKind::HostFn { line, .. }
+            | ErrorKind::NoExtension { line, .. }
+            | ErrorKind::Extension { line, .. }
+            | ErrorKind::NotAllowed { line, .. }
+            | ErrorKind::JumpOutOfRange { line, .. }
+            | ErrorKind::Cycle { line, .. } => *line,
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Uninitialized { register, line } => {
+                write!(f, "Register {register} must be initialized on line: {line}")
+            }
+            ErrorKind::BothUninitialized { x, y, line } => write!(
+                f,
+                "Both registers {x} and {y} must be initialized on line: {line}"
+            ),
+            ErrorKind::UninitializedString { register, line } => write!(
+                f,
+                "String register {register} must be initialized on line: {line}"
+            ),
+            ErrorKind::DivisionByZero { line } => write!(f, "Division by zero on line: {line}"),
+            ErrorKind::Unprintable { value, line } => {
+                write!(f, "Value {value} is not a character, failed to print it on line: {line}")
+            }
+            ErrorKind::Output { message, line } => {
+                write!(f, "Failed to write output on line: {line}: {message}")
+            }
+            ErrorKind::Input { message, line } => {
+                write!(f, "Failed to read input on line: {line}: {message}")
+            }
+            ErrorKind::StringTooLong { register, line } => {
+                write!(f, "String register {register} is too long on line: {line}")
+            }
+            ErrorKind::NoDevice { port, line } => {
+                write!(f, "No device attached to port {port} on line: {line}")
+            }
+            ErrorKind::NoHostFn { n, line } => {
+                write!(f, "No host function {n} on line: {line}")
+            }
+            ErrorKind::HostFn { n, message, line } => {
+                write!(f, "Host function {n} failed on line: {line}: {message}")
+            }
+            ErrorKind::NoExtension { name, line } => {
+                write!(f, "No extension {name} on line: {line}")
+            }
+            ErrorKind::Extension {
+                name,
+                message,
+                line,
+            } => write!(f, "Extension {name} failed on line: {line}: {message}"),
+            ErrorKind::NotAllowed {
+                capability,
+                port,
+                line,
+            } => write!(
+                f,
+                "Program is not allowed to use {capability} (device on port {port}) on line: {line}"
+            ),
+            ErrorKind::JumpOutOfRange { offset, line } => write!(
+                f,
+                "Jump by {offset} leaves the program on line: {line}"
+            ),
+            ErrorKind::Cycle { state, steps, line } => write!(
+                f,
+                "Non-terminating cycle detected on line: {line}, the VM came back to {state} after {steps} steps"
+            ),
+        }
+    }
+}
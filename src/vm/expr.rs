@@ -0,0 +1,376 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use super::parser::{ConstOrReg, Constant, Instruction, Register};
+
+#[derive(Debug, PartialEq)]
+pub enum ExprError {
+    UnexpectedChar(usize, char),
+    UnexpectedToken(usize, String),
+    UnexpectedEnd,
+    Unsupported(String),
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExprError::UnexpectedChar(pos, ch) => {
+                write!(f, "column {}: unexpected character '{ch}'", pos + 1)
+            }
+            ExprError::UnexpectedToken(pos, token) => {
+                write!(f, "column {}: unexpected {token}", pos + 1)
+            }
+            ExprError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ExprError::Unsupported(what) => write!(f, "{what} is not supported by the VM"),
+        }
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(i32),
+    Ident(String),
+    Op(char),
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let mut tokens = Vec::new();
+    let chars = s.char_indices().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, ch) = chars[i];
+        if ch.is_whitespace() {
+            i += 1;
+        } else if ch.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].1.is_ascii_digit() {
+                i += 1;
+            }
+            let text = chars[start..i].iter().map(|(_, c)| c).collect::<String>();
+            let num = text
+                .parse::<i32>()
+                .map_err(|_| ExprError::UnexpectedToken(pos, text))?;
+            tokens.push((pos, Token::Num(num)));
+        } else if ch.is_alphabetic() {
+            let start = i;
+            while i < chars.len() && chars[i].1.is_alphabetic() {
+                i += 1;
+            }
+            let text = chars[start..i].iter().map(|(_, c)| c).collect::<String>();
+            tokens.push((pos, Token::Ident(text)));
+        } else if "+-*/()=".contains(ch) {
+            tokens.push((pos, Token::Op(ch)));
+            i += 1;
+        } else {
+            return Err(ExprError::UnexpectedChar(pos, ch));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Num(i32),
+    Var(String),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn collect_vars<'a>(&'a self, vars: &mut HashSet<&'a str>) {
+        match self {
+            Expr::Num(_) => (),
+            Expr::Var(name) => {
+                vars.insert(name);
+            }
+            Expr::Add(l, r) | Expr::Sub(l, r) | Expr::Mul(l, r) | Expr::Div(l, r) => {
+                l.collect_vars(vars);
+                r.collect_vars(vars);
+            }
+        }
+    }
+}
+
+struct ExprParser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), ExprError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or(ExprError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect_op(&mut self, op: char) -> Result<(), ExprError> {
+        match self.next()? {
+            (_, Token::Op(ch)) if ch == op => Ok(()),
+            (pos, token) => Err(ExprError::UnexpectedToken(pos, format!("{token:?}"))),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ExprError> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = match op {
+                '+' => Expr::Add(Box::new(lhs), Box::new(rhs)),
+                _ => Expr::Sub(Box::new(lhs), Box::new(rhs)),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, ExprError> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            let rhs = self.factor()?;
+            lhs = match op {
+                '*' => Expr::Mul(Box::new(lhs), Box::new(rhs)),
+                _ => Expr::Div(Box::new(lhs), Box::new(rhs)),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, ExprError> {
+        match self.next()? {
+            (_, Token::Num(num)) => Ok(Expr::Num(num)),
+            (_, Token::Ident(name)) => Ok(Expr::Var(name)),
+            (_, Token::Op('(')) => {
+                let inner = self.expr()?;
+                self.expect_op(')')?;
+                Ok(inner)
+            }
+            (pos, Token::Op('-')) => match self.next()? {
+                (_, Token::Num(num)) => Ok(Expr::Num(-num)),
+                (_, token) => Err(ExprError::UnexpectedToken(pos, format!("{token:?}"))),
+            },
+            (pos, token) => Err(ExprError::UnexpectedToken(pos, format!("{token:?}"))),
+        }
+    }
+}
+
+/// Parses `name = expression` into the assigned register and its expression tree.
+pub(crate) fn parse_assignment(s: &str) -> Result<(String, Expr), ExprError> {
+    let mut parser = ExprParser {
+        tokens: tokenize(s)?,
+        pos: 0,
+    };
+    let target = match parser.next()? {
+        (_, Token::Ident(name)) => name,
+        (pos, token) => return Err(ExprError::UnexpectedToken(pos, format!("{token:?}"))),
+    };
+    parser.expect_op('=')?;
+    let expr = parser.expr()?;
+    match parser.tokens.get(parser.pos) {
+        Some((pos, token)) => Err(ExprError::UnexpectedToken(*pos, format!("{token:?}"))),
+        None => Ok((target, expr)),
+    }
+}
+
+/// Lowers expression trees to instructions, allocating temporaries that do not clash
+/// with any register in `reserved`.
+pub(crate) struct Codegen {
+    pub(crate) instructions: Vec<Instruction>,
+    reserved: HashSet<String>,
+    temps: usize,
+}
+
+impl Codegen {
+    pub(crate) fn new(reserved: HashSet<String>) -> Self {
+        Codegen {
+            instructions: Vec::new(),
+            reserved,
+            temps: 0,
+        }
+    }
+
+    fn temp(&mut self) -> Register {
+        loop {
+            let mut n = self.temps;
+            self.temps += 1;
+            // registers have to be alphabetic, so number temporaries in base 26
+            let mut name = String::from("t");
+            loop {
+                name.push((b'a' + (n % 26) as u8) as char);
+                n /= 26;
+                if n == 0 {
+                    break;
+                }
+            }
+            if !self.reserved.contains(&name) {
+                return Register::of(name);
+            }
+        }
+    }
+
+    pub(crate) fn load_register(&mut self, value: ConstOrReg) -> Register {
+        match value {
+            ConstOrReg::Reg(reg) => reg,
+            constant => {
+                let tmp = self.temp();
+                self.instructions
+                    .push(Instruction::Mov(tmp.clone(), constant));
+                tmp
+            }
+        }
+    }
+
+    /// Emits code computing `expr` and returns where the result lives.
+    pub(crate) fn expr(&mut self, expr: &Expr) -> Result<ConstOrReg, ExprError> {
+        match expr {
+            Expr::Num(num) => Ok(ConstOrReg::Const(Constant::of(*num))),
+            Expr::Var(name) => Ok(ConstOrReg::Reg(Register::of(name.clone()))),
+            Expr::Add(l, r) => self.binary(
+                l,
+                r,
+                |l, r| Some(Constant::of(l.wrapping_add(*r))),
+                Instruction::Add,
+            ),
+            Expr::Sub(l, r) => self.binary(
+                l,
+                r,
+                |l, r| Some(Constant::of(l.wrapping_sub(*r))),
+                Instruction::Sub,
+            ),
+            Expr::Mul(l, r) => self.binary(
+                l,
+                r,
+                |l, r| Some(Constant::of(l.wrapping_mul(*r))),
+                Instruction::Mul,
+            ),
+            // dividing by a constant zero is left to trap at runtime
+            Expr::Div(l, r) => self.binary(l, r, Constant::quotient, Instruction::Div),
+        }
+    }
+
+    /// Emits `op` on `l` and `r`, or folds them with `fold` when both are constants.
+    fn binary(
+        &mut self,
+        l: &Expr,
+        r: &Expr,
+        fold: fn(Constant, Constant) -> Option<Constant>,
+        op: fn(Register, Register) -> Instruction,
+    ) -> Result<ConstOrReg, ExprError> {
+        let l = self.expr(l)?;
+        let r = self.expr(r)?;
+        if let (ConstOrReg::Const(x), ConstOrReg::Const(y)) = (&l, &r) {
+            if let Some(value) = fold(*x, *y) {
+                return Ok(ConstOrReg::Const(value));
+            }
+        }
+        // intermediate results are used exactly once, so a temporary can be reused in place
+        let tmp = match l {
+            ConstOrReg::Reg(reg) if !self.reserved.contains(&reg.to_string()) => reg,
+            l => {
+                let tmp = self.temp();
+                self.instructions.push(Instruction::Mov(tmp.clone(), l));
+                tmp
+            }
+        };
+        let r = self.load_register(r);
+        self.instructions.push(op(tmp.clone(), r));
+        Ok(ConstOrReg::Reg(tmp))
+    }
+}
+
+/// Compiles an assignment like `a = (b + 3) * c` into instructions,
+/// using temporary registers for intermediate values.
+pub fn compile_expr(s: &str) -> Result<Vec<Instruction>, ExprError> {
+    let (target, expr) = parse_assignment(s)?;
+    let mut vars = HashSet::new();
+    expr.collect_vars(&mut vars);
+    let mut reserved = vars.into_iter().map(String::from).collect::<HashSet<_>>();
+    reserved.insert(target.clone());
+
+    let mut codegen = Codegen::new(reserved);
+    let value = codegen.expr(&expr)?;
+    codegen
+        .instructions
+        .push(Instruction::Mov(Register::of(target), value));
+    Ok(codegen.instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_compile_and_run() {
+        let mut instructions = parse_instructions(vec!["mov b 4", "mov c 10"]).unwrap();
+        instructions.extend(compile_expr("a = (b + 3) + c - 2").unwrap());
+        let a = Register::of("a".to_string());
+
+        let mut vm = Vm::new();
+        vm.interpret(&instructions.into()).unwrap();
+        assert_eq!(*vm.registers.get(&a).unwrap(), Constant::of(15));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut instructions = parse_instructions(vec!["mov b 4", "mov c 10"]).unwrap();
+        instructions.extend(compile_expr("a = (b + 3) * c - 20 / b").unwrap());
+        let a = Register::of("a".to_string());
+
+        let mut vm = Vm::new();
+        vm.interpret(&instructions.into()).unwrap();
+        assert_eq!(*vm.registers.get(&a).unwrap(), Constant::of(65));
+    }
+
+    #[test]
+    fn test_constants_are_folded() {
+        assert_eq!(
+            compile_expr("a = 1 + (2 - -3) * 4 / 2").unwrap(),
+            vec![Instruction::Mov(
+                Register::of("a".to_string()),
+                ConstOrReg::Const(Constant::of(11))
+            )]
+        );
+        assert_eq!(compile_expr("a = 1 / 0").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_temporaries_avoid_variables() {
+        let instructions = compile_expr("ta = ta + tb").unwrap();
+        assert_eq!(
+            instructions[0],
+            Instruction::Mov(
+                Register::of("tc".to_string()),
+                ConstOrReg::Reg(Register::of("ta".to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(compile_expr("a = (b + 1"), Err(ExprError::UnexpectedEnd));
+        assert_eq!(
+            compile_expr("a = b % 2"),
+            Err(ExprError::UnexpectedChar(6, '%'))
+        );
+        assert_eq!(
+            compile_expr("a = b % 2").unwrap_err().to_string(),
+            "column 7: unexpected character '%'"
+        );
+    }
+}
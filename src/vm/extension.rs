@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use super::host::VmState;
+use super::parser::ConstOrReg;
+
+// Instructions added by the embedder. Parse with the registry, then hand it to the VM:
+//
+//   let mut extensions = Extensions::default();
+//   extensions.register("sqrt", Box::new(Sqrt));
+//   let program = parse_program_with(lines, &extensions)?;
+//   vm.set_extensions(extensions);
+
+/// Executor of an instruction added under its own mnemonic, parsed as
+/// [`super::parser::Instruction::Custom`].
+pub trait Extension: Send {
+    /// Number of operands, each a register or a constant.
+    fn arity(&self) -> usize;
+    /// Runs the instruction, an error stops the program with the message.
+    fn execute(&mut self, state: &mut VmState, operands: &[ConstOrReg]) -> Result<(), String>;
+}
+
+/// Extensions by mnemonic, consulted by the parser for lines no built-in instruction matches.
+#[derive(Default)]
+pub struct Extensions {
+    extensions: HashMap<String, Box<dyn Extension>>,
+}
+
+impl Extensions {
+    /// Adds `extension` as `name`, built-in mnemonics with the same arity take precedence.
+    pub fn register(&mut self, name: &str, extension: Box<dyn Extension>) {
+        self.extensions.insert(name.to_string(), extension);
+    }
+
+    pub fn arity(&self, name: &str) -> Option<usize> {
+        self.extensions.get(name).map(|extension| extension.arity())
+    }
+
+    pub(super) fn take(&mut self, name: &str) -> Option<(String, Box<dyn Extension>)> {
+        self.extensions.remove_entry(name)
+    }
+
+    pub(super) fn put(&mut self, name: String, extension: Box<dyn Extension>) {
+        self.extensions.insert(name, extension);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::error::ErrorKind;
+    use crate::vm::parser::{parse_program_with, Constant, Instruction, ParseError};
+    use crate::vm::Vm;
+
+    /// `clamp x lo hi`
+    struct Clamp;
+
+    impl Extension for Clamp {
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn execute(&mut self, state: &mut VmState, operands: &[ConstOrReg]) -> Result<(), String> {
+            let [ConstOrReg::Reg(x), lo, hi] = operands else {
+                return Err("clamp expects a register first".to_string());
+            };
+            let value = |operand| state.load(operand).ok_or("uninitialized operand");
+            let clamped = value(&ConstOrReg::Reg(x.clone()))?.clamp(value(lo)?, value(hi)?);
+            state.store(x, clamped);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_extension() {
+        let mut extensions = Extensions::default();
+        extensions.register("clamp", Box::new(Clamp));
+        let program = parse_program_with(
+            vec!["mov a 40", "mov b 9", "clamp a 0 b", "clamp 1 2 3"],
+            &extensions,
+        )
+        .unwrap();
+        assert_eq!(program[2].to_string(), "clamp a 0 b");
+        assert!(matches!(program[2], Instruction::Custom(..)));
+        assert!(matches!(
+            parse_program_with(vec!["clamp a 0"], &extensions),
+            Err(ParseError::InstructionNotFoundOrWrongArgs(_))
+        ));
+
+        let mut vm = Vm::new();
+        let err = vm.interpret(&program).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::NoExtension { line: 3, .. }));
+
+        let mut vm = Vm::new();
+        vm.set_extensions(extensions);
+        let err = vm.interpret(&program).unwrap_err();
+        assert_eq!(vm.get(&"a".parse().unwrap()), Some(Constant::of(9)));
+        assert_eq!(
+            err.to_string(),
+            "Extension clamp failed on line: 4: clamp expects a register first"
+        );
+    }
+}
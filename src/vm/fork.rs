@@ -0,0 +1,62 @@
+use std::collections::VecDeque;
+
+use super::Vm;
+
+impl Vm {
+    /// Copies the machine state, so that the copy can run on from here without affecting
+    /// this VM, e.g. to try several branches in a search. Registers are shared until one
+    /// side writes them, which makes a fork cost the same whatever their number.
+    ///
+    /// Only the state and the settings that are plain values are copied: the fork has no
+    /// devices, host functions, extensions, handlers, tracer or coverage, and reads and
+    /// writes stdin and stdout.
+    pub fn fork(&self) -> Vm {
+        let mut fork = Vm::new();
+        fork.registers = self.registers.clone();
+        fork.strings = self.strings.clone();
+        fork.carry = self.carry;
+        fork.flags = self.flags;
+        fork.pc = self.pc;
+        fork.max_len = self.max_len;
+        fork.program = self.program.clone();
+        fork.capabilities = self.capabilities.clone();
+        fork.budget = self.budget;
+        fork.jumps = self.jumps.clone();
+        fork.jump_history = self.jump_history;
+        fork.history = VecDeque::clone(&self.history);
+        fork.history_len = self.history_len;
+        fork
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::vm::parser::{parse_program, Constant, Register};
+    use crate::vm::{StepOutcome, Vm};
+
+    #[test]
+    fn test_fork() {
+        let program = parse_program(vec!["mov a 5", "in b 0", "add a b"]).unwrap();
+        let mut vm = Vm::new();
+        vm.start(&program, 0);
+        vm.step(&program).unwrap();
+        // Tries both answers of the missing device.
+        let branches = [1, 2].map(|b| {
+            let mut fork = vm.fork();
+            assert!(Arc::ptr_eq(&fork.registers, &vm.registers));
+            fork.set_register(&Register::of("b".to_string()), Constant::of(b));
+            fork.pc += 1;
+            assert_eq!(fork.step(&program), Ok(StepOutcome::Continued));
+            fork.get(&Register::of("a".to_string())).unwrap()
+        });
+        assert_eq!(branches, [6, 7].map(Constant::of));
+        assert_eq!(
+            vm.get(&Register::of("a".to_string())),
+            Some(Constant::of(5))
+        );
+        assert_eq!(vm.get(&Register::of("b".to_string())), None);
+        assert_eq!(vm.pc, 1);
+    }
+}
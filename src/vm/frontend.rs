@@ -0,0 +1,466 @@
+use std::{collections::HashSet, fmt::Display, mem};
+
+use super::expr::{Codegen, Expr, ExprError};
+use super::parser::{ConstOrReg, Constant, Instruction};
+
+// Compiles a small structured language to VM instructions:
+//
+//   let n = 5;
+//   while n { print n + 48; n = n - 1; }
+//   if n { print 89; } else { print 78; }
+//
+// Conditions are true when non zero, `print` writes the character with the given code
+// and `#` starts a comment.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FrontendErrorKind {
+    UnexpectedChar(char),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UndeclaredVariable(String),
+    Unsupported(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FrontendError {
+    pub span: Span,
+    pub kind: FrontendErrorKind,
+}
+
+impl Display for FrontendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            FrontendErrorKind::UnexpectedChar(ch) => {
+                write!(f, "{}: unexpected character '{ch}'", self.span)
+            }
+            FrontendErrorKind::UnexpectedToken(token) => {
+                write!(f, "{}: unexpected {token}", self.span)
+            }
+            FrontendErrorKind::UnexpectedEnd => write!(f, "{}: unexpected end of input", self.span),
+            FrontendErrorKind::UndeclaredVariable(name) => {
+                write!(f, "{}: variable {name} is used before `let`", self.span)
+            }
+            FrontendErrorKind::Unsupported(what) => {
+                write!(f, "{}: {what} is not supported by the VM", self.span)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrontendError {}
+
+fn error<T>(span: Span, kind: FrontendErrorKind) -> Result<T, FrontendError> {
+    Err(FrontendError { span, kind })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(i32),
+    Ident(String),
+    Let,
+    If,
+    Else,
+    While,
+    Print,
+    Sym(char),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(num) => write!(f, "number {num}"),
+            Token::Ident(name) => write!(f, "identifier {name}"),
+            Token::Let => write!(f, "`let`"),
+            Token::If => write!(f, "`if`"),
+            Token::Else => write!(f, "`else`"),
+            Token::While => write!(f, "`while`"),
+            Token::Print => write!(f, "`print`"),
+            Token::Sym(ch) => write!(f, "'{ch}'"),
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Span, Token)>, FrontendError> {
+    let mut tokens = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut j = 0;
+        while j < chars.len() {
+            let span = Span {
+                line: i + 1,
+                col: j + 1,
+            };
+            let ch = chars[j];
+            if ch == '#' {
+                break;
+            } else if ch.is_whitespace() {
+                j += 1;
+            } else if ch.is_ascii_digit() {
+                let start = j;
+                while j < chars.len() && chars[j].is_ascii_digit() {
+                    j += 1;
+                }
+                let text = chars[start..j].iter().collect::<String>();
+                match text.parse::<i32>() {
+                    Ok(num) => tokens.push((span, Token::Num(num))),
+                    Err(_) => return error(span, FrontendErrorKind::UnexpectedToken(text)),
+                }
+            } else if ch.is_alphabetic() {
+                let start = j;
+                while j < chars.len() && chars[j].is_alphabetic() {
+                    j += 1;
+                }
+                let token = match chars[start..j].iter().collect::<String>().as_str() {
+                    "let" => Token::Let,
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "while" => Token::While,
+                    "print" => Token::Print,
+                    name => Token::Ident(name.to_string()),
+                };
+                tokens.push((span, token));
+            } else if "=+-*/(){};".contains(ch) {
+                tokens.push((span, Token::Sym(ch)));
+                j += 1;
+            } else {
+                return error(span, FrontendErrorKind::UnexpectedChar(ch));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, PartialEq)]
+enum Stmt {
+    Assign(String, Expr, Span),
+    Print(Expr, Span),
+    If(Expr, Span, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Span, Vec<Stmt>),
+}
+
+struct Parser {
+    tokens: Vec<(Span, Token)>,
+    pos: usize,
+    declared: HashSet<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn span(&self) -> Span {
+        match self.tokens.get(self.pos).or(self.tokens.last()) {
+            Some((span, _)) => *span,
+            None => Span { line: 1, col: 1 },
+        }
+    }
+
+    fn next(&mut self) -> Result<(Span, Token), FrontendError> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token)
+            }
+            None => error(self.span(), FrontendErrorKind::UnexpectedEnd),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), FrontendError> {
+        match self.next()? {
+            (_, token) if token == expected => Ok(()),
+            (span, token) => error(span, FrontendErrorKind::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Stmt>, FrontendError> {
+        let mut stmts = Vec::new();
+        while self.peek().is_some() {
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, FrontendError> {
+        self.expect(Token::Sym('{'))?;
+        let mut stmts = Vec::new();
+        while self.peek() != Some(&Token::Sym('}')) {
+            stmts.push(self.stmt()?);
+        }
+        self.expect(Token::Sym('}'))?;
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, FrontendError> {
+        match self.next()? {
+            (_, Token::Let) => {
+                let (span, token) = self.next()?;
+                let Token::Ident(name) = token else {
+                    return error(span, FrontendErrorKind::UnexpectedToken(token.to_string()));
+                };
+                self.expect(Token::Sym('='))?;
+                let (expr, span) = self.expr()?;
+                self.expect(Token::Sym(';'))?;
+                self.declared.insert(name.clone());
+                Ok(Stmt::Assign(name, expr, span))
+            }
+            (span, Token::Ident(name)) => {
+                if !self.declared.contains(&name) {
+                    return error(span, FrontendErrorKind::UndeclaredVariable(name));
+                }
+                self.expect(Token::Sym('='))?;
+                let (expr, span) = self.expr()?;
+                self.expect(Token::Sym(';'))?;
+                Ok(Stmt::Assign(name, expr, span))
+            }
+            (_, Token::Print) => {
+                let (expr, span) = self.expr()?;
+                self.expect(Token::Sym(';'))?;
+                Ok(Stmt::Print(expr, span))
+            }
+            (_, Token::If) => {
+                let (cond, span) = self.expr()?;
+                let then = self.block()?;
+                let otherwise = if self.peek() == Some(&Token::Else) {
+                    self.pos += 1;
+                    self.block()?
+                } else {
+                    Vec::new()
+                };
+                Ok(Stmt::If(cond, span, then, otherwise))
+            }
+            (_, Token::While) => {
+                let (cond, span) = self.expr()?;
+                let body = self.block()?;
+                Ok(Stmt::While(cond, span, body))
+            }
+            (span, token) => error(span, FrontendErrorKind::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn expr(&mut self) -> Result<(Expr, Span), FrontendError> {
+        let span = self.span();
+        let mut lhs = self.term()?;
+        while let Some(Token::Sym(op @ ('+' | '-'))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = match op {
+                '+' => Expr::Add(Box::new(lhs), Box::new(rhs)),
+                _ => Expr::Sub(Box::new(lhs), Box::new(rhs)),
+            };
+        }
+        Ok((lhs, span))
+    }
+
+    fn term(&mut self) -> Result<Expr, FrontendError> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Sym(op @ ('*' | '/'))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            let rhs = self.factor()?;
+            lhs = match op {
+                '*' => Expr::Mul(Box::new(lhs), Box::new(rhs)),
+                _ => Expr::Div(Box::new(lhs), Box::new(rhs)),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, FrontendError> {
+        match self.next()? {
+            (_, Token::Num(num)) => Ok(Expr::Num(num)),
+            (span, Token::Ident(name)) => {
+                if !self.declared.contains(&name) {
+                    return error(span, FrontendErrorKind::UndeclaredVariable(name));
+                }
+                Ok(Expr::Var(name))
+            }
+            (_, Token::Sym('(')) => {
+                let (inner, _) = self.expr()?;
+                self.expect(Token::Sym(')'))?;
+                Ok(inner)
+            }
+            (_, Token::Sym('-')) => match self.next()? {
+                (_, Token::Num(num)) => Ok(Expr::Num(-num)),
+                (span, _) => error(
+                    span,
+                    FrontendErrorKind::Unsupported("negating a variable".to_string()),
+                ),
+            },
+            (span, token) => error(span, FrontendErrorKind::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+struct Compiler {
+    codegen: Codegen,
+}
+
+impl Compiler {
+    fn expr(&mut self, expr: &Expr, span: Span) -> Result<ConstOrReg, FrontendError> {
+        self.codegen.expr(expr).or_else(|err| match err {
+            ExprError::Unsupported(what) => error(span, FrontendErrorKind::Unsupported(what)),
+            err => error(span, FrontendErrorKind::UnexpectedToken(format!("{err:?}"))),
+        })
+    }
+
+    /// Compiles statements into a fresh instruction list, so jumps around it can be sized.
+    fn block(&mut self, stmts: &[Stmt]) -> Result<Vec<Instruction>, FrontendError> {
+        let outer = mem::take(&mut self.codegen.instructions);
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(mem::replace(&mut self.codegen.instructions, outer))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), FrontendError> {
+        match stmt {
+            Stmt::Assign(name, expr, span) => {
+                let value = self.expr(expr, *span)?;
+                self.codegen
+                    .instructions
+                    .push(Instruction::Mov(name.as_str().parse().unwrap(), value));
+            }
+            Stmt::Print(expr, span) => {
+                let value = self.expr(expr, *span)?;
+                let reg = self.codegen.load_register(value);
+                self.codegen.instructions.push(Instruction::Print(reg));
+            }
+            Stmt::If(cond, span, then, otherwise) => {
+                let cond = self.expr(cond, *span)?;
+                let then = self.block(then)?;
+                let otherwise = self.block(otherwise)?;
+                // the then branch ends with a jump over the else branch when there is one
+                let skip_then = then.len() + if otherwise.is_empty() { 1 } else { 2 };
+                self.jz(cond, skip_then as i32);
+                self.codegen.instructions.extend(then);
+                if !otherwise.is_empty() {
+                    self.jump(otherwise.len() as i32 + 1);
+                    self.codegen.instructions.extend(otherwise);
+                }
+            }
+            Stmt::While(cond, span, body) => {
+                let start = self.codegen.instructions.len();
+                let cond = self.expr(cond, *span)?;
+                let body = self.block(body)?;
+                self.jz(cond, body.len() as i32 + 2);
+                self.codegen.instructions.extend(body);
+                let back = self.codegen.instructions.len() - start;
+                self.jump(-(back as i32));
+            }
+        }
+        Ok(())
+    }
+
+    fn jz(&mut self, cond: ConstOrReg, offset: i32) {
+        self.codegen.instructions.push(Instruction::Jz(
+            cond,
+            ConstOrReg::Const(Constant::of(offset)),
+        ));
+    }
+
+    fn jump(&mut self, offset: i32) {
+        self.codegen
+            .instructions
+            .push(Instruction::Jmp(ConstOrReg::Const(Constant::of(offset))));
+    }
+}
+
+/// Compiles a program in the structured language to VM instructions.
+pub fn compile(src: &str) -> Result<Vec<Instruction>, FrontendError> {
+    let tokens = tokenize(src)?;
+    let reserved = tokens
+        .iter()
+        .filter_map(|(_, token)| match token {
+            Token::Ident(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        declared: HashSet::new(),
+    };
+    let stmts = parser.program()?;
+
+    let mut compiler = Compiler {
+        codegen: Codegen::new(reserved),
+    };
+    compiler.block(&stmts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::Register;
+    use crate::vm::Vm;
+
+    fn run(src: &str) -> Vm {
+        let mut vm = Vm::new();
+        vm.interpret(&compile(src).unwrap().into()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let vm = run("let n = 5;\nlet sum = 0;\nwhile n {\n  sum = sum + n;\n  n = n - 1;\n}");
+        let sum = Register::of("sum".to_string());
+        assert_eq!(vm.registers.get(&sum), Some(&Constant::of(15)));
+    }
+
+    #[test]
+    fn test_if_else() {
+        let vm = run("let a = 0;\nlet b = 0;\nif a { b = 1; } else { b = 2; }\nif b { a = 3; }");
+        let a = Register::of("a".to_string());
+        let b = Register::of("b".to_string());
+        assert_eq!(vm.registers.get(&a), Some(&Constant::of(3)));
+        assert_eq!(vm.registers.get(&b), Some(&Constant::of(2)));
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        let src = "let i = 3;\nlet total = 0;\nwhile i {\n  let j = 2;\n  while j {\n    total = total + 1;\n    j = j - 1;\n  }\n  i = i - 1;\n}";
+        let vm = run(src);
+        let total = Register::of("total".to_string());
+        assert_eq!(vm.registers.get(&total), Some(&Constant::of(6)));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let vm = run("let a = 6;\nlet b = a * 7 / 2 - a;");
+        let b = Register::of("b".to_string());
+        assert_eq!(vm.registers.get(&b), Some(&Constant::of(15)));
+    }
+
+    #[test]
+    fn test_spanned_errors() {
+        assert_eq!(
+            compile("let a = 1;\nb = a;"),
+            Err(FrontendError {
+                span: Span { line: 2, col: 1 },
+                kind: FrontendErrorKind::UndeclaredVariable("b".to_string()),
+            })
+        );
+        assert_eq!(
+            compile("let a = 1;\nprint -a;").unwrap_err().to_string(),
+            "2:8: negating a variable is not supported by the VM"
+        );
+        assert_eq!(
+            compile("let a = 1 $").unwrap_err().span,
+            Span { line: 1, col: 11 }
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use super::error::VmError;
+#[cfg(feature = "metrics")]
+use super::metrics;
+use super::parser::Program;
+use super::state::State;
+use super::{ExitStatus, StepOutcome, Vm};
+
+#[derive(Default)]
+struct Requests {
+    paused: bool,
+    stop: bool,
+    inspect: bool,
+    reload: Option<Program>,
+    /// Outcome of the latest reload, set once the worker has served it.
+    reloaded: Option<Result<(), String>>,
+    /// Latest state published by the worker.
+    state: Option<State>,
+    finished: bool,
+}
+
+#[derive(Default)]
+struct Control {
+    /// Set with every request so the worker only takes the lock when there is something to do.
+    attention: AtomicBool,
+    requests: Mutex<Requests>,
+    changed: Condvar,
+}
+
+impl Control {
+    fn request(&self, update: impl FnOnce(&mut Requests)) {
+        update(&mut self.requests.lock().unwrap());
+        self.attention.store(true, Ordering::SeqCst);
+        self.changed.notify_all();
+    }
+
+    /// Serves pending requests between two instructions, returns false when the VM should stop.
+    fn safe_point(&self, vm: &mut Vm, program: &mut Program) -> bool {
+        if !self.attention.swap(false, Ordering::SeqCst) {
+            return true;
+        }
+        let mut requests = self.requests.lock().unwrap();
+        loop {
+            if let Some(reloaded) = requests.reload.take() {
+                // without labels there is no telling which new line matches the current one
+                requests.reloaded = Some(if vm.pc < reloaded.len() {
+                    *program = reloaded;
+                    vm.start_program(program, vm.pc);
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "the VM is on line {}, past the end of the {} line program",
+                        vm.pc + 1,
+                        reloaded.len()
+                    ))
+                });
+                self.changed.notify_all();
+            }
+            if requests.inspect {
+                requests.inspect = false;
+                requests.state = Some(State::of(vm, program));
+                self.changed.notify_all();
+            }
+            if requests.stop {
+                return false;
+            }
+            if !requests.paused {
+                return true;
+            }
+            requests = self.changed.wait(requests).unwrap();
+        }
+    }
+}
+
+/// Marks the run as finished even when the worker thread panics, e.g. in a device.
+struct Finish<'a>(&'a Control);
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut requests) = self.0.requests.lock() {
+            requests.finished = true;
+        }
+        self.0.changed.notify_all();
+    }
+}
+
+/// Controls a VM running on a worker thread, requests are served between two instructions.
+#[derive(Clone)]
+pub struct VmHandle {
+    control: Arc<Control>,
+}
+
+impl VmHandle {
+    pub fn pause(&self) {
+        self.control.request(|requests| requests.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.control.request(|requests| requests.paused = false);
+    }
+
+    /// Ends the run at the next instruction boundary, a paused VM is stopped as well.
+    pub fn stop(&self) {
+        self.control.request(|requests| requests.stop = true);
+    }
+
+    /// Swaps in `program` at the next instruction boundary without waiting for it, the VM
+    /// keeps its registers and carries on from the same line. See [`VmHandle::reloaded`] for
+    /// the outcome.
+    pub fn reload(&self, program: Program) {
+        self.control.request(|requests| {
+            requests.reload = Some(program);
+            requests.reloaded = None;
+        });
+    }
+
+    /// Outcome of the latest reload once the worker has served it, reported only once. Fails,
+    /// keeping the old program, when the current line doesn't exist in the new program or the
+    /// run finished first.
+    pub fn reloaded(&self) -> Option<Result<(), String>> {
+        let mut requests = self.control.requests.lock().unwrap();
+        if requests.finished && requests.reload.take().is_some() {
+            return Some(Err("the run has already finished".to_string()));
+        }
+        requests.reloaded.take()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.control.requests.lock().unwrap().finished
+    }
+
+    /// Current pc and registers, waiting for the worker to reach the next instruction boundary.
+    /// Once the run has finished this is the final state, `None` if the worker thread panicked
+    /// before publishing anything.
+    pub fn inspect(&self) -> Option<State> {
+        self.control.request(|requests| requests.inspect = true);
+        let mut requests = self.control.requests.lock().unwrap();
+        while requests.inspect && !requests.finished {
+            requests = self.control.changed.wait(requests).unwrap();
+        }
+        requests.state.clone()
+    }
+}
+
+/// The VM and how its run ended, returned by the worker thread of [`Vm::spawn`].
+pub type Finished = (Vm, Result<ExitStatus, VmError>);
+
+impl Vm {
+    /// Runs `program` from its entry on a worker thread, the thread returns the VM and how the
+    /// run ended once the program ends, fails or is stopped.
+    pub fn spawn(mut self, mut program: Program) -> (VmHandle, JoinHandle<Finished>) {
+        let control = Arc::new(Control::default());
+        let handle = VmHandle {
+            control: control.clone(),
+        };
+        let worker = thread::spawn(move || {
+            let _finish = Finish(&control);
+            #[cfg(feature = "metrics")]
+            let _running = metrics::Running::start();
+            self.start_program(&program, program.entry);
+            let result = loop {
+                if !control.safe_point(&mut self, &mut program) {
+                    break Ok(ExitStatus::Stopped);
+                }
+                match self.step(&program) {
+                    Ok(StepOutcome::Continued) => {}
+                    Ok(StepOutcome::Halted) => break Ok(ExitStatus::Completed),
+                    Ok(StepOutcome::BudgetExhausted) => break Ok(ExitStatus::BudgetExhausted),
+                    Err(err) => break Err(err),
+                }
+            };
+            control.requests.lock().unwrap().state = Some(State::of(&self, &program));
+            (self, result)
+        });
+        (handle, worker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::device::Device;
+    use crate::vm::parser::{parse_program, Constant, Register};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    /// Announces every read on the sender, then blocks until a value is sent.
+    struct Gate(Sender<()>, Receiver<Constant>);
+
+    impl Device for Gate {
+        fn read(&mut self) -> Constant {
+            self.0.send(()).unwrap();
+            self.1.recv().unwrap()
+        }
+
+        fn write(&mut self, _value: Constant) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    /// Spins until the worker has served the latest reload.
+    fn outcome(handle: &VmHandle) -> Result<(), String> {
+        loop {
+            if let Some(result) = handle.reloaded() {
+                return result;
+            }
+        }
+    }
+
+    #[test]
+    fn test_runs_to_completion() {
+        let program = parse_program(vec!["mov a 3", "mov b -1", "add a b", "jnz a -1"]);
+        let (handle, worker) = Vm::new().spawn(program.unwrap());
+        let (vm, result) = worker.join().unwrap();
+        assert_eq!(result, Ok(ExitStatus::Completed));
+        assert!(handle.is_finished());
+        assert_eq!(vm.pc, 4);
+        assert_eq!(
+            handle.inspect().unwrap().to_string(),
+            "halted, a = 0, b = -1, carry"
+        );
+    }
+
+    #[test]
+    fn test_pause_inspect_resume_stop() {
+        // Counts up forever.
+        let program = parse_program(vec!["mov a 0", "mov b 1", "add a b", "jnz 1 -1"]).unwrap();
+        let (handle, worker) = Vm::new().spawn(program.clone());
+        handle.pause();
+        let first = handle.inspect().unwrap();
+        assert_eq!(handle.inspect().unwrap(), first);
+        handle.resume();
+        while handle.inspect().unwrap() == first {}
+        handle.stop();
+        let (vm, result) = worker.join().unwrap();
+        assert_eq!(result, Ok(ExitStatus::Stopped));
+        assert!(handle.is_finished());
+        assert_eq!(handle.inspect().unwrap(), State::of(&vm, &program));
+    }
+
+    #[test]
+    fn test_reload() {
+        // Counts up forever until the loop is replaced, whatever line it was on.
+        let program = parse_program(vec!["mov a 0", "mov b 1", "add a b", "jnz 1 -1"]);
+        let (handle, worker) = Vm::new().spawn(program.unwrap());
+        let reloaded = parse_program(vec!["mov a 0", "mov b 1", "mov c 7", "mov c 7"]);
+        handle.reload(reloaded.unwrap());
+        assert_eq!(outcome(&handle), Ok(()));
+        assert_eq!(handle.reloaded(), None);
+        let (vm, _) = worker.join().unwrap();
+        let state = handle.inspect().unwrap();
+        assert_eq!(state.pc, None);
+        assert!(state.registers.contains(&("b".to_string(), 1)));
+        assert!(state.registers.contains(&("c".to_string(), 7)));
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_reload_past_the_end() {
+        let program = parse_program(vec!["mov a 1", "jnz 1 0"]).unwrap();
+        let (handle, worker) = Vm::new().spawn(program);
+        while handle.inspect().unwrap().pc != Some(1) {}
+        handle.reload(parse_program(vec!["mov c 7"]).unwrap());
+        assert_eq!(
+            outcome(&handle),
+            Err("the VM is on line 2, past the end of the 1 line program".to_string())
+        );
+        handle.stop();
+        let (_, result) = worker.join().unwrap();
+        assert_eq!(result, Ok(ExitStatus::Stopped));
+        handle.reload(parse_program(vec!["mov c 7"]).unwrap());
+        assert_eq!(
+            outcome(&handle),
+            Err("the run has already finished".to_string())
+        );
+    }
+
+    #[test]
+    fn test_reload_does_not_wait_for_a_blocked_read() {
+        let (entered, reading) = channel();
+        let (send, receive) = channel();
+        let mut vm = Vm::new();
+        vm.attach_device(Constant::of(5), Box::new(Gate(entered, receive)));
+        let (handle, worker) = vm.spawn(parse_program(vec!["in a 5", "mov b a"]).unwrap());
+        reading.recv().unwrap();
+        handle.reload(parse_program(vec!["in a 5", "mov c a"]).unwrap());
+        assert_eq!(handle.reloaded(), None);
+        send.send(Constant::of(3)).unwrap();
+        assert_eq!(outcome(&handle), Ok(()));
+        let (vm, result) = worker.join().unwrap();
+        assert_eq!(result, Ok(ExitStatus::Completed));
+        assert_eq!(
+            vm.get(&Register::of("c".to_string())),
+            Some(Constant::of(3))
+        );
+        assert_eq!(vm.get(&Register::of("b".to_string())), None);
+    }
+
+    #[test]
+    fn test_trap_finishes_the_run() {
+        let (handle, worker) = Vm::new().spawn(parse_program(vec!["out 7 a"]).unwrap());
+        let (_, result) = worker.join().unwrap();
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Register a must be initialized on line: 1"
+        );
+        assert!(handle.is_finished());
+        assert_eq!(handle.inspect().unwrap().pc, Some(0));
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use super::parser::{ConstOrReg, Constant, Register};
+use super::Vm;
+
+/// Function the host exposes to programs as `syscall n`, see [`Vm::register_host_fn`]. An
+/// error stops the program with the message.
+pub type HostFn = Box<dyn FnMut(&mut VmState) -> Result<(), String> + Send>;
+
+/// What a host function may see and change of the VM calling it. Arguments and results
+/// are passed in registers by whatever convention the host and the program agree on.
+pub struct VmState<'a> {
+    pub(super) vm: &'a mut Vm,
+}
+
+impl VmState<'_> {
+    pub fn get(&self, register: &str) -> Option<Constant> {
+        self.vm.get(&Register::of(register.to_string()))
+    }
+
+    pub fn set(&mut self, register: &str, value: Constant) {
+        self.store(&Register::of(register.to_string()), value);
+    }
+
+    /// Value of a constant or register operand, `None` for an uninitialized register.
+    pub fn load(&self, operand: &ConstOrReg) -> Option<Constant> {
+        match operand {
+            ConstOrReg::Const(constant) => Some(*constant),
+            ConstOrReg::Reg(register) => self.vm.get(register),
+        }
+    }
+
+    pub fn store(&mut self, register: &Register, value: Constant) {
+        self.vm.set_register(register, value);
+    }
+
+    pub fn string(&self, register: &str) -> Option<&str> {
+        self.vm
+            .strings
+            .get(&Register::of(register.to_string()))
+            .map(String::as_str)
+    }
+
+    pub fn set_string(&mut self, register: &str, text: &str) {
+        Arc::make_mut(&mut self.vm.strings)
+            .insert(Register::of(register.to_string()), text.to_string());
+    }
+
+    /// 1-based line of the instruction being served.
+    pub fn line(&self) -> usize {
+        self.vm.line()
+    }
+}
+
+impl Vm {
+    /// Makes `syscall n` call `host_fn`, replacing any function registered for `n`.
+    pub fn register_host_fn(
+        &mut self,
+        n: i32,
+        host_fn: impl FnMut(&mut VmState) -> Result<(), String> + Send + 'static,
+    ) {
+        self.host_fns.insert(Constant::of(n), Box::new(host_fn));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::error::ErrorKind;
+    use crate::vm::parser::{parse_program, Constant};
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_host_fns() {
+        let program = parse_program(vec![
+            "mov a 9",
+            "syscall 1",
+            "syscall 2",
+            "mov b -1",
+            "syscall 2",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.register_host_fn(1, |state| {
+            let a = state.get("a").ok_or("a is not set")?;
+            state.set("b", Constant::of(a.isqrt()));
+            Ok(())
+        });
+        vm.register_host_fn(2, |state| match state.get("b") {
+            Some(b) if *b < 0 => Err(format!("negative b on line {}", state.line())),
+            Some(b) => {
+                state.set_string("s", &b.to_string());
+                Ok(())
+            }
+            None => Err("b is not set".to_string()),
+        });
+        let err = vm.interpret(&program).unwrap_err();
+        assert_eq!(vm.get(&"b".parse().unwrap()), Some(Constant::of(-1)));
+        assert_eq!(vm.strings[&"s".parse().unwrap()], "3");
+        assert_eq!(
+            err.kind,
+            ErrorKind::HostFn {
+                n: Constant::of(2),
+                message: "negative b on line 5".to_string(),
+                line: 5
+            }
+        );
+        let err = Vm::new()
+            .interpret(&parse_program(vec!["syscall 7"]).unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "No host function 7 on line: 1");
+    }
+}
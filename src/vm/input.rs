@@ -0,0 +1,22 @@
+use std::io::{ErrorKind, Read};
+
+/// Where `read` takes its bytes from, stdin unless the host sets another source with
+/// [`super::Vm::set_input`]. Anything implementing [`Read`] is an input source.
+pub trait InputSource: Send {
+    /// The next byte, `None` at the end of the input.
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>>;
+}
+
+impl<R: Read + Send> InputSource for R {
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            match self.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
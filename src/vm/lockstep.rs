@@ -0,0 +1,138 @@
+use std::fmt::Display;
+
+use super::error::VmError;
+use super::parser::Instruction;
+pub use super::state::State;
+use super::Vm;
+
+#[derive(Debug, PartialEq)]
+pub enum Lockstep {
+    /// Both sides ended after `steps` instructions with the same state.
+    Agree { steps: usize },
+    /// States differed after `step` instructions.
+    Diverged {
+        step: usize,
+        left: State,
+        right: State,
+    },
+    /// Both sides still agreed when the step limit was reached.
+    StepLimit,
+    /// At least one side failed while executing step `step`.
+    Trapped {
+        step: usize,
+        left: Option<VmError>,
+        right: Option<VmError>,
+    },
+}
+
+impl Display for Lockstep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lockstep::Agree { steps } => write!(f, "both ended in agreement after {steps} steps"),
+            Lockstep::Diverged { step, left, right } => {
+                write!(
+                    f,
+                    "diverged after step {step}\n  left:  {left}\n  right: {right}"
+                )
+            }
+            Lockstep::StepLimit => write!(f, "still in agreement when the step limit was reached"),
+            Lockstep::Trapped { step, left, right } => {
+                let side = |err: &Option<VmError>| {
+                    err.as_ref()
+                        .map_or_else(|| "no error".to_string(), VmError::to_string)
+                };
+                write!(
+                    f,
+                    "trapped in step {step}\n  left:  {}\n  right: {}",
+                    side(left),
+                    side(right)
+                )
+            }
+        }
+    }
+}
+
+/// Runs two VMs one instruction at a time, comparing pc and registers after every step.
+pub fn lockstep(
+    left: (&mut Vm, &[Instruction]),
+    right: (&mut Vm, &[Instruction]),
+    max_steps: usize,
+) -> Lockstep {
+    let (left_vm, left_instructions) = left;
+    let (right_vm, right_instructions) = right;
+    left_vm.start(left_instructions, 0);
+    right_vm.start(right_instructions, 0);
+    for step in 0..=max_steps {
+        let left_state = State::of(left_vm, left_instructions);
+        let right_state = State::of(right_vm, right_instructions);
+        if left_state != right_state {
+            return Lockstep::Diverged {
+                step,
+                left: left_state,
+                right: right_state,
+            };
+        }
+        if left_state.pc.is_none() {
+            return Lockstep::Agree { steps: step };
+        }
+        if step < max_steps {
+            let left = left_vm.step(left_instructions).err();
+            let right = right_vm.step(right_instructions).err();
+            if left.is_some() || right.is_some() {
+                return Lockstep::Trapped {
+                    step: step + 1,
+                    left,
+                    right,
+                };
+            }
+        }
+    }
+    Lockstep::StepLimit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_instructions;
+
+    fn run(left: Vec<&str>, right: Vec<&str>, max_steps: usize) -> Lockstep {
+        let left = parse_instructions(left).unwrap();
+        let right = parse_instructions(right).unwrap();
+        lockstep((&mut Vm::new(), &left), (&mut Vm::new(), &right), max_steps)
+    }
+
+    #[test]
+    fn test_agree() {
+        let program = vec!["mov a 2", "mov b -1", "add a b", "jnz a -1"];
+        assert_eq!(
+            run(program.clone(), program.clone(), 100),
+            Lockstep::Agree { steps: 6 }
+        );
+        assert_eq!(run(program.clone(), program, 3), Lockstep::StepLimit);
+    }
+
+    #[test]
+    fn test_diverged() {
+        let result = run(
+            vec!["mov a 2", "mov b -1", "add a b"],
+            vec!["mov a 2", "mov b 1", "add a b"],
+            100,
+        );
+        assert_eq!(
+            result.to_string(),
+            "diverged after step 2\n  left:  line 3, a = 2, b = -1\n  right: line 3, a = 2, b = 1"
+        );
+        assert_eq!(
+            run(vec!["mov a 1"], vec!["mov a 1", "mov a 2"], 100).to_string(),
+            "diverged after step 1\n  left:  halted, a = 1\n  right: line 2, a = 1"
+        );
+        assert_eq!(
+            run(vec![r#"smov s "a""#], vec![r#"smov s "b""#], 100).to_string(),
+            "diverged after step 1\n  left:  halted, s = \"a\"\n  right: halted, s = \"b\""
+        );
+        assert_eq!(
+            run(vec!["mov a 1", "mov a b"], vec!["mov a 1", "mov a 1"], 100).to_string(),
+            "trapped in step 2\n  left:  Register b must be initialized on line: 2\n  right: no error"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+};
+
+// Process wide counters shared by every Vm, so a host running many machines
+// can expose a single scrape endpoint.
+
+static INSTRUCTIONS_EXECUTED: AtomicU64 = AtomicU64::new(0);
+static TRAPS: AtomicU64 = AtomicU64::new(0);
+static RUNNING_VMS: AtomicI64 = AtomicI64::new(0);
+
+pub(crate) fn instruction_executed() {
+    INSTRUCTIONS_EXECUTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn trap() {
+    TRAPS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a VM as running until dropped, including when a trap unwinds.
+pub(crate) struct Running;
+
+impl Running {
+    pub(crate) fn start() -> Self {
+        RUNNING_VMS.fetch_add(1, Ordering::Relaxed);
+        Running
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING_VMS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Current values in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} {kind}").unwrap();
+        writeln!(out, "{name} {value}").unwrap();
+    };
+    metric(
+        "simple_vm_instructions_executed_total",
+        "counter",
+        "Instructions executed by all VMs.",
+        INSTRUCTIONS_EXECUTED.load(Ordering::Relaxed).to_string(),
+    );
+    metric(
+        "simple_vm_traps_total",
+        "counter",
+        "Runtime errors raised by all VMs.",
+        TRAPS.load(Ordering::Relaxed).to_string(),
+    );
+    metric(
+        "simple_vm_running",
+        "gauge",
+        "VMs currently interpreting a program.",
+        RUNNING_VMS.load(Ordering::Relaxed).to_string(),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_program;
+    use crate::vm::Vm;
+
+    fn value(name: &str) -> i64 {
+        render()
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_counters() {
+        // other tests run in parallel, so only check for growth
+        let before = value("simple_vm_instructions_executed_total");
+        let traps = value("simple_vm_traps_total");
+        let instructions = parse_program(vec!["mov a 1", "add a a"]).unwrap();
+        let mut vm = Vm::new();
+        vm.interpret(&instructions).unwrap();
+        let result = vm.interpret(&parse_program(vec!["out 9 a"]).unwrap());
+        assert!(result.is_err());
+        assert!(value("simple_vm_instructions_executed_total") >= before + 3);
+        assert!(value("simple_vm_traps_total") > traps);
+        assert!(render().contains("# TYPE simple_vm_running gauge\n"));
+    }
+}
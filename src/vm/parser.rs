@@ -1,4 +1,6 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, sync::Arc};
+
+use super::extension::Extensions;
 
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub struct Register(String);
@@ -37,7 +39,7 @@ impl FromStr for Register {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Constant(i32);
 
 impl Constant {
@@ -45,6 +47,83 @@ impl Constant {
         Constant(v)
     }
     pub const ZERO: Constant = Constant(0);
+
+    /// Unsigned sum of the two words and the carry, with the carry out of bit 31.
+    pub fn add_with_carry(self, rhs: Constant, carry: bool) -> (Constant, bool) {
+        let (sum, first) = (self.0 as u32).overflowing_add(rhs.0 as u32);
+        let (sum, second) = sum.overflowing_add(carry as u32);
+        (Constant(sum as i32), first || second)
+    }
+
+    /// Unsigned difference of the two words minus the borrow, with the borrow into bit 31.
+    pub fn sub_with_borrow(self, rhs: Constant, borrow: bool) -> (Constant, bool) {
+        let (difference, first) = (self.0 as u32).overflowing_sub(rhs.0 as u32);
+        let (difference, second) = difference.overflowing_sub(borrow as u32);
+        (Constant(difference as i32), first || second)
+    }
+
+    /// High 32 bits of the unsigned 64 bit product of the two words.
+    pub fn mul_high(self, rhs: Constant) -> Constant {
+        Constant(((self.0 as u32 as u64 * rhs.0 as u32 as u64) >> 32) as i32)
+    }
+
+    /// Product of two Q16.16 fixed-point numbers, wrapping like `add` does.
+    pub fn fx_mul(self, rhs: Constant) -> Constant {
+        Constant(((self.0 as i64 * rhs.0 as i64) >> 16) as i32)
+    }
+
+    /// Value of `field`, shifted down to bit 0.
+    pub fn bit_extract(self, field: BitField) -> Constant {
+        Constant(((self.0 as u32 & field.mask()) >> field.offset) as i32)
+    }
+
+    /// Copy with `field` replaced by the low bits of `src`.
+    pub fn bit_insert(self, src: Constant, field: BitField) -> Constant {
+        let mask = field.mask();
+        Constant(((self.0 as u32 & !mask) | ((src.0 as u32) << field.offset & mask)) as i32)
+    }
+
+    /// Bits shifted left by `amount`, amounts outside `0..32` shift every bit out.
+    pub fn shift_left(self, amount: Constant) -> Constant {
+        let amount = u32::try_from(amount.0).unwrap_or(u32::MAX);
+        Constant((self.0 as u32).checked_shl(amount).unwrap_or(0) as i32)
+    }
+
+    /// Bits shifted right by `amount` with zeros coming in, amounts outside `0..32` shift
+    /// every bit out.
+    pub fn shift_right(self, amount: Constant) -> Constant {
+        let amount = u32::try_from(amount.0).unwrap_or(u32::MAX);
+        Constant((self.0 as u32).checked_shr(amount).unwrap_or(0) as i32)
+    }
+
+    /// Bits rotated left by `amount`, modulo 32, so negative amounts rotate right.
+    pub fn rotate_left(self, amount: Constant) -> Constant {
+        Constant((self.0 as u32).rotate_left(amount.0 as u32 % 32) as i32)
+    }
+
+    /// Bits rotated right by `amount`, modulo 32, so negative amounts rotate left.
+    pub fn rotate_right(self, amount: Constant) -> Constant {
+        Constant((self.0 as u32).rotate_right(amount.0 as u32 % 32) as i32)
+    }
+
+    /// Quotient rounded towards zero, wrapping like `add` does, `None` when dividing by zero.
+    pub fn quotient(self, rhs: Constant) -> Option<Constant> {
+        (rhs.0 != 0).then(|| Constant(self.0.wrapping_div(rhs.0)))
+    }
+
+    /// Remainder of [`Constant::quotient`], with the sign of `self`, `None` when dividing
+    /// by zero.
+    pub fn remainder(self, rhs: Constant) -> Option<Constant> {
+        (rhs.0 != 0).then(|| Constant(self.0.wrapping_rem(rhs.0)))
+    }
+
+    /// Quotient of two Q16.16 fixed-point numbers, `None` when dividing by zero.
+    pub fn fx_div(self, rhs: Constant) -> Option<Constant> {
+        if rhs.0 == 0 {
+            return None;
+        }
+        Some(Constant((((self.0 as i64) << 16) / rhs.0 as i64) as i32))
+    }
 }
 
 impl std::ops::Add for Constant {
@@ -55,6 +134,38 @@ impl std::ops::Add for Constant {
     }
 }
 
+impl std::ops::BitAnd for Constant {
+    type Output = Constant;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Constant(self.0 & rhs.0)
+    }
+}
+
+impl std::ops::BitOr for Constant {
+    type Output = Constant;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Constant(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitXor for Constant {
+    type Output = Constant;
+
+    fn bitxor(self, rhs: Self) -> Self::Output {
+        Constant(self.0 ^ rhs.0)
+    }
+}
+
+impl std::ops::Not for Constant {
+    type Output = Constant;
+
+    fn not(self) -> Self::Output {
+        Constant(!self.0)
+    }
+}
+
 impl From<i32> for Constant {
     fn from(value: i32) -> Self {
         Constant::of(value)
@@ -82,6 +193,95 @@ impl Display for Constant {
     }
 }
 
+/// Bits `offset..offset + len` of a 32 bit word, validated when parsed so that
+/// `0 < len` and `offset + len <= 32`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitField {
+    pub offset: u32,
+    pub len: u32,
+}
+
+impl BitField {
+    fn new(offset: Constant, len: Constant) -> Option<Self> {
+        let (offset, len) = (u32::try_from(*offset).ok()?, u32::try_from(*len).ok()?);
+        (len > 0 && offset.checked_add(len)? <= 32).then_some(BitField { offset, len })
+    }
+
+    /// The field's bits set, in place.
+    pub fn mask(self) -> u32 {
+        (u32::MAX >> (32 - self.len)) << self.offset
+    }
+}
+
+impl Display for BitField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.offset, self.len)
+    }
+}
+
+/// Flags set by `cmp x y`, which the conditional jumps test.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flags {
+    /// `x` equals `y`.
+    pub zero: bool,
+    /// `x` is less than `y`, i.e. the exact difference `x - y` is negative.
+    pub negative: bool,
+}
+
+impl Flags {
+    pub fn compare(x: Constant, y: Constant) -> Self {
+        Flags {
+            zero: x == y,
+            negative: x < y,
+        }
+    }
+}
+
+/// When a conditional jump like `jg` is taken, given the flags of the latest `cmp`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+}
+
+impl Condition {
+    pub const ALL: [Condition; 6] = [
+        Condition::Equal,
+        Condition::NotEqual,
+        Condition::Greater,
+        Condition::Less,
+        Condition::GreaterOrEqual,
+        Condition::LessOrEqual,
+    ];
+
+    /// The mnemonic of the jump taken under this condition.
+    pub fn opcode(self) -> &'static str {
+        match self {
+            Condition::Equal => "je",
+            Condition::NotEqual => "jne",
+            Condition::Greater => "jg",
+            Condition::Less => "jl",
+            Condition::GreaterOrEqual => "jge",
+            Condition::LessOrEqual => "jle",
+        }
+    }
+
+    pub fn holds(self, flags: Flags) -> bool {
+        match self {
+            Condition::Equal => flags.zero,
+            Condition::NotEqual => !flags.zero,
+            Condition::Greater => !flags.zero && !flags.negative,
+            Condition::Less => flags.negative,
+            Condition::GreaterOrEqual => !flags.negative,
+            Condition::LessOrEqual => flags.zero || flags.negative,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConstOrReg {
     Const(Constant),
@@ -91,10 +291,19 @@ pub enum ConstOrReg {
 impl FromStr for ConstOrReg {
     type Err = Box<dyn std::error::Error>;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.parse::<Constant>().map_or(
-            s.parse::<Register>().map(|reg| ConstOrReg::Reg(reg)),
-            |cn| Ok(ConstOrReg::Const(cn)),
-        )
+        s.parse::<Constant>()
+            .map_or(s.parse::<Register>().map(ConstOrReg::Reg), |cn| {
+                Ok(ConstOrReg::Const(cn))
+            })
+    }
+}
+
+impl Display for ConstOrReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConstOrReg::Const(constant) => write!(f, "{constant}"),
+            ConstOrReg::Reg(register) => write!(f, "{register}"),
+        }
     }
 }
 
@@ -102,8 +311,215 @@ impl FromStr for ConstOrReg {
 pub enum Instruction {
     Mov(Register, ConstOrReg),
     Add(Register, Register),
+    /// `sub x y` subtracts `y` from `x`, setting the carry on borrow like `add` does.
+    Sub(Register, Register),
+    /// `mul x y` stores the low 32 bits of the product of `x` and `y` in `x`.
+    Mul(Register, Register),
+    /// `div x y` stores the quotient of `x` and `y` rounded towards zero in `x`.
+    Div(Register, Register),
+    /// `mod x y` stores the remainder of `div x y` in `x`, with the sign of `x`.
+    Mod(Register, Register),
     Jnz(ConstOrReg, ConstOrReg),
+    /// `jz x offset` jumps by `offset` like `jnz` when `x` is zero.
+    Jz(ConstOrReg, ConstOrReg),
+    /// `jmp offset` always jumps by `offset`.
+    Jmp(ConstOrReg),
+    /// `cmp x y` sets the flags from comparing `x` with `y`, see [`Flags`].
+    Cmp(Register, ConstOrReg),
+    /// `je offset`, `jg offset`, ... jump by `offset` like `jnz` when the condition holds
+    /// for the flags of the latest `cmp`.
+    JumpIf(Condition, ConstOrReg),
     Print(Register),
+    In(Register, Constant),
+    Out(Constant, Register),
+    Poll(Register, Constant),
+    /// String registers live apart from the integer ones, `smov s "text"`.
+    SMov(Register, String),
+    /// Appends the second string register to the first.
+    SCat(Register, Register),
+    /// Stores the number of characters of a string register in an integer register.
+    SLen(Register, Register),
+    SPrint(Register),
+    /// Q16.16 fixed-point multiplication, `fxmul x y` stores the product in `x`.
+    FxMul(Register, Register),
+    /// Q16.16 fixed-point division, `fxdiv x y` stores the quotient in `x`.
+    FxDiv(Register, Register),
+    /// `bext x y offset len` stores the bit field of `y` in `x`, shifted down to bit 0.
+    BExt(Register, Register, BitField),
+    /// `bins x y offset len` replaces the bit field of `x` with the low bits of `y`.
+    BIns(Register, Register, BitField),
+    /// `and x n` stores the bitwise and of `x` and `n` in `x`.
+    And(Register, ConstOrReg),
+    /// `or x n` stores the bitwise or of `x` and `n` in `x`.
+    Or(Register, ConstOrReg),
+    /// `xor x n` stores the bitwise exclusive or of `x` and `n` in `x`.
+    Xor(Register, ConstOrReg),
+    /// `not x` flips every bit of `x`.
+    Not(Register),
+    /// `shl x n` shifts the bits of `x` left by `n`, 0 once `n` is outside `0..32`.
+    Shl(Register, ConstOrReg),
+    /// `shr x n` shifts the bits of `x` right by `n` with zeros coming in, 0 once `n` is
+    /// outside `0..32`.
+    Shr(Register, ConstOrReg),
+    /// `rol x n` rotates the bits of `x` left by `n`.
+    Rol(Register, ConstOrReg),
+    /// `ror x n` rotates the bits of `x` right by `n`.
+    Ror(Register, ConstOrReg),
+    /// `popcnt x y` stores the number of set bits of `y` in `x`.
+    Popcnt(Register, Register),
+    /// `clz x y` stores the number of leading zero bits of `y` in `x`, 32 for zero.
+    Clz(Register, Register),
+    /// `ctz x y` stores the number of trailing zero bits of `y` in `x`, 32 for zero.
+    Ctz(Register, Register),
+    /// `mulh x y` stores the high half of the unsigned 64 bit product of `x` and `y` in `x`.
+    MulH(Register, Register),
+    /// `addc x y` adds `y` and the carry flag to `x`, setting the carry on unsigned overflow.
+    AddC(Register, Register),
+    /// `subb x y` subtracts `y` and the carry flag from `x`, setting the carry on borrow.
+    SubB(Register, Register),
+    /// `emit name x` hands the event `name` with the value of `x` to the host.
+    Emit(String, Register),
+    /// `read x` stores the next byte of the VM input in `x`, -1 at the end of the input.
+    Read(Register),
+    /// `syscall n` calls the host function registered for `n`, which may read and write
+    /// any register.
+    Syscall(Constant),
+    /// `name operands...` runs the extension registered as `name`, see
+    /// [`super::extension::Extensions`].
+    Custom(String, Vec<ConstOrReg>),
+}
+
+impl Instruction {
+    /// Every mnemonic, the device instructions `in`, `out` and `poll` come last.
+    pub const OPCODES: [&'static str; 45] = [
+        "mov", "add", "sub", "mul", "div", "mod", "addc", "subb", "mulh", "fxmul", "fxdiv", "bext",
+        "bins", "and", "or", "xor", "not", "shl", "shr", "rol", "ror", "popcnt", "clz", "ctz",
+        "jnz", "jz", "jmp", "cmp", "je", "jne", "jg", "jl", "jge", "jle", "print", "read", "smov",
+        "scat", "slen", "sprint", "emit", "syscall", "in", "out", "poll",
+    ];
+
+    /// Offset of a jump, relative to the jump's pc.
+    pub fn jump_offset(&self) -> Option<&ConstOrReg> {
+        match self {
+            Instruction::Jnz(_, offset)
+            | Instruction::Jz(_, offset)
+            | Instruction::Jmp(offset)
+            | Instruction::JumpIf(_, offset) => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// The mnemonic the instruction is written with.
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            Instruction::Mov(..) => "mov",
+            Instruction::Add(..) => "add",
+            Instruction::Sub(..) => "sub",
+            Instruction::Mul(..) => "mul",
+            Instruction::Div(..) => "div",
+            Instruction::Mod(..) => "mod",
+            Instruction::Jnz(..) => "jnz",
+            Instruction::Jz(..) => "jz",
+            Instruction::Jmp(..) => "jmp",
+            Instruction::Cmp(..) => "cmp",
+            Instruction::JumpIf(condition, _) => condition.opcode(),
+            Instruction::Print(..) => "print",
+            Instruction::In(..) => "in",
+            Instruction::Out(..) => "out",
+            Instruction::Poll(..) => "poll",
+            Instruction::SMov(..) => "smov",
+            Instruction::SCat(..) => "scat",
+            Instruction::SLen(..) => "slen",
+            Instruction::SPrint(..) => "sprint",
+            Instruction::FxMul(..) => "fxmul",
+            Instruction::FxDiv(..) => "fxdiv",
+            Instruction::BExt(..) => "bext",
+            Instruction::BIns(..) => "bins",
+            Instruction::And(..) => "and",
+            Instruction::Or(..) => "or",
+            Instruction::Xor(..) => "xor",
+            Instruction::Not(..) => "not",
+            Instruction::Shl(..) => "shl",
+            Instruction::Shr(..) => "shr",
+            Instruction::Rol(..) => "rol",
+            Instruction::Ror(..) => "ror",
+            Instruction::Popcnt(..) => "popcnt",
+            Instruction::Clz(..) => "clz",
+            Instruction::Ctz(..) => "ctz",
+            Instruction::MulH(..) => "mulh",
+            Instruction::AddC(..) => "addc",
+            Instruction::SubB(..) => "subb",
+            Instruction::Emit(..) => "emit",
+            Instruction::Read(..) => "read",
+            Instruction::Syscall(..) => "syscall",
+            Instruction::Custom(..) => "custom",
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::Mov(x, y) => write!(f, "mov {x} {y}"),
+            Instruction::Add(x, y) => write!(f, "add {x} {y}"),
+            Instruction::Sub(x, y) => write!(f, "sub {x} {y}"),
+            Instruction::Mul(x, y) => write!(f, "mul {x} {y}"),
+            Instruction::Div(x, y) => write!(f, "div {x} {y}"),
+            Instruction::Mod(x, y) => write!(f, "mod {x} {y}"),
+            Instruction::Jnz(x, y) => write!(f, "jnz {x} {y}"),
+            Instruction::Jz(x, y) => write!(f, "jz {x} {y}"),
+            Instruction::Jmp(offset) => write!(f, "jmp {offset}"),
+            Instruction::Cmp(x, y) => write!(f, "cmp {x} {y}"),
+            Instruction::JumpIf(condition, offset) => write!(f, "{} {offset}", condition.opcode()),
+            Instruction::Print(x) => write!(f, "print {x}"),
+            Instruction::Read(x) => write!(f, "read {x}"),
+            Instruction::Syscall(n) => write!(f, "syscall {n}"),
+            Instruction::Custom(name, operands) => {
+                write!(f, "{name}")?;
+                for operand in operands {
+                    write!(f, " {operand}")?;
+                }
+                Ok(())
+            }
+            Instruction::In(x, port) => write!(f, "in {x} {port}"),
+            Instruction::Out(port, x) => write!(f, "out {port} {x}"),
+            Instruction::Poll(x, port) => write!(f, "poll {x} {port}"),
+            Instruction::SMov(x, text) => {
+                write!(f, "smov {x} \"")?;
+                for ch in text.chars() {
+                    match ch {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        _ => write!(f, "{ch}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Instruction::SCat(x, y) => write!(f, "scat {x} {y}"),
+            Instruction::SLen(x, y) => write!(f, "slen {x} {y}"),
+            Instruction::SPrint(x) => write!(f, "sprint {x}"),
+            Instruction::FxMul(x, y) => write!(f, "fxmul {x} {y}"),
+            Instruction::FxDiv(x, y) => write!(f, "fxdiv {x} {y}"),
+            Instruction::BExt(x, y, field) => write!(f, "bext {x} {y} {field}"),
+            Instruction::BIns(x, y, field) => write!(f, "bins {x} {y} {field}"),
+            Instruction::And(x, n) => write!(f, "and {x} {n}"),
+            Instruction::Or(x, n) => write!(f, "or {x} {n}"),
+            Instruction::Xor(x, n) => write!(f, "xor {x} {n}"),
+            Instruction::Not(x) => write!(f, "not {x}"),
+            Instruction::Shl(x, n) => write!(f, "shl {x} {n}"),
+            Instruction::Shr(x, n) => write!(f, "shr {x} {n}"),
+            Instruction::Rol(x, n) => write!(f, "rol {x} {n}"),
+            Instruction::Ror(x, n) => write!(f, "ror {x} {n}"),
+            Instruction::Popcnt(x, y) => write!(f, "popcnt {x} {y}"),
+            Instruction::Clz(x, y) => write!(f, "clz {x} {y}"),
+            Instruction::Ctz(x, y) => write!(f, "ctz {x} {y}"),
+            Instruction::MulH(x, y) => write!(f, "mulh {x} {y}"),
+            Instruction::AddC(x, y) => write!(f, "addc {x} {y}"),
+            Instruction::SubB(x, y) => write!(f, "subb {x} {y}"),
+            Instruction::Emit(name, x) => write!(f, "emit {name} {x}"),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -125,12 +541,60 @@ where
     })
 }
 
+/// Parses a double quoted literal with `\"`, `\\` and `\n` escapes.
+fn parse_string_literal(s: &str) -> Result<String, ParseError> {
+    let error = || {
+        ParseError::IncorrectArgument(format!(
+            "Failed to parse {s}, expected a string in double quotes"
+        ))
+    };
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(error)?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some(escaped @ ('"' | '\\')) => text.push(escaped),
+                _ => return Err(error()),
+            },
+            '"' => return Err(error()),
+            _ => text.push(ch),
+        }
+    }
+    Ok(text)
+}
+
 pub fn parse_instructions(input: Vec<&str>) -> Result<Vec<Instruction>, ParseError> {
+    parse_instructions_with(input, &Extensions::default())
+}
+
+/// Parses `input` like [`parse_instructions`], also accepting the instructions of
+/// `extensions`.
+pub fn parse_instructions_with(
+    input: Vec<&str>,
+    extensions: &Extensions,
+) -> Result<Vec<Instruction>, ParseError> {
     if input.is_empty() {
         return Result::Err(ParseError::EmptyInput);
     }
     let mut instructions: Vec<Instruction> = Vec::new();
     for (i, line) in input.iter().enumerate() {
+        // the literal may contain whitespace, so it is taken as the rest of the line
+        let smov = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .filter(|(op, _)| *op == "smov")
+            .and_then(|(_, rest)| rest.trim_start().split_once(char::is_whitespace));
+        if let Some((x, text)) = smov {
+            let x_reg = parse_token(x)?;
+            let text = parse_string_literal(text.trim())?;
+            instructions.push(Instruction::SMov(x_reg, text));
+            continue;
+        }
         let parts = line.split_ascii_whitespace().collect::<Vec<_>>();
         match parts[..] {
             ["mov", x, y] => {
@@ -143,15 +607,186 @@ pub fn parse_instructions(input: Vec<&str>) -> Result<Vec<Instruction>, ParseErr
                 let y_reg = parse_token(y)?;
                 instructions.push(Instruction::Add(x_reg, y_reg))
             }
+            [op @ ("sub" | "mul" | "div" | "mod"), x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(match op {
+                    "sub" => Instruction::Sub(x_reg, y_reg),
+                    "mul" => Instruction::Mul(x_reg, y_reg),
+                    "div" => Instruction::Div(x_reg, y_reg),
+                    _ => Instruction::Mod(x_reg, y_reg),
+                })
+            }
             ["print", x] => {
                 let x_reg = parse_token(x)?;
                 instructions.push(Instruction::Print(x_reg))
             }
+            ["read", x] => {
+                let x_reg = parse_token(x)?;
+                instructions.push(Instruction::Read(x_reg))
+            }
+            ["syscall", n] => {
+                let n = parse_token(n)?;
+                instructions.push(Instruction::Syscall(n))
+            }
             ["jnz", x, y] => {
                 let x_reg = parse_token(x)?;
                 let y_reg = parse_token(y)?;
                 instructions.push(Instruction::Jnz(x_reg, y_reg))
             }
+            ["jz", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::Jz(x_reg, y_reg))
+            }
+            ["jmp", offset] => {
+                let offset = parse_token(offset)?;
+                instructions.push(Instruction::Jmp(offset))
+            }
+            ["cmp", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::Cmp(x_reg, y_reg))
+            }
+            [op @ ("je" | "jne" | "jg" | "jl" | "jge" | "jle"), offset] => {
+                let condition = match op {
+                    "je" => Condition::Equal,
+                    "jne" => Condition::NotEqual,
+                    "jg" => Condition::Greater,
+                    "jl" => Condition::Less,
+                    "jge" => Condition::GreaterOrEqual,
+                    _ => Condition::LessOrEqual,
+                };
+                let offset = parse_token(offset)?;
+                instructions.push(Instruction::JumpIf(condition, offset))
+            }
+            ["in", x, port] => {
+                let x_reg = parse_token(x)?;
+                let port = parse_token(port)?;
+                instructions.push(Instruction::In(x_reg, port))
+            }
+            ["out", port, x] => {
+                let port = parse_token(port)?;
+                let x_reg = parse_token(x)?;
+                instructions.push(Instruction::Out(port, x_reg))
+            }
+            ["poll", x, port] => {
+                let x_reg = parse_token(x)?;
+                let port = parse_token(port)?;
+                instructions.push(Instruction::Poll(x_reg, port))
+            }
+            ["scat", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::SCat(x_reg, y_reg))
+            }
+            ["slen", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::SLen(x_reg, y_reg))
+            }
+            ["fxmul", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::FxMul(x_reg, y_reg))
+            }
+            ["fxdiv", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::FxDiv(x_reg, y_reg))
+            }
+            [op @ ("bext" | "bins"), x, y, offset, len] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                let field =
+                    BitField::new(parse_token(offset)?, parse_token(len)?).ok_or_else(|| {
+                        ParseError::IncorrectArgument(format!(
+                            "Bit field {offset} {len} on line {i} does not fit in 32 bits"
+                        ))
+                    })?;
+                instructions.push(match op {
+                    "bext" => Instruction::BExt(x_reg, y_reg, field),
+                    _ => Instruction::BIns(x_reg, y_reg, field),
+                })
+            }
+            [op @ ("and" | "or" | "xor" | "shl" | "shr"), x, n] => {
+                let x_reg = parse_token(x)?;
+                let operand = parse_token(n)?;
+                instructions.push(match op {
+                    "and" => Instruction::And(x_reg, operand),
+                    "or" => Instruction::Or(x_reg, operand),
+                    "xor" => Instruction::Xor(x_reg, operand),
+                    "shl" => Instruction::Shl(x_reg, operand),
+                    _ => Instruction::Shr(x_reg, operand),
+                })
+            }
+            ["not", x] => {
+                let x_reg = parse_token(x)?;
+                instructions.push(Instruction::Not(x_reg))
+            }
+            ["rol", x, n] => {
+                let x_reg = parse_token(x)?;
+                let amount = parse_token(n)?;
+                instructions.push(Instruction::Rol(x_reg, amount))
+            }
+            ["ror", x, n] => {
+                let x_reg = parse_token(x)?;
+                let amount = parse_token(n)?;
+                instructions.push(Instruction::Ror(x_reg, amount))
+            }
+            ["popcnt", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::Popcnt(x_reg, y_reg))
+            }
+            ["clz", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::Clz(x_reg, y_reg))
+            }
+            ["ctz", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::Ctz(x_reg, y_reg))
+            }
+            ["mulh", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::MulH(x_reg, y_reg))
+            }
+            ["addc", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::AddC(x_reg, y_reg))
+            }
+            ["subb", x, y] => {
+                let x_reg = parse_token(x)?;
+                let y_reg = parse_token(y)?;
+                instructions.push(Instruction::SubB(x_reg, y_reg))
+            }
+            ["emit", name, x] => {
+                if !name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+                {
+                    return Err(ParseError::IncorrectArgument(format!(
+                        "Event name {name} on line {i} may only contain letters, digits, '_', '.' and '-'"
+                    )));
+                }
+                let x_reg = parse_token(x)?;
+                instructions.push(Instruction::Emit(name.to_string(), x_reg))
+            }
+            ["sprint", x] => {
+                let x_reg = parse_token(x)?;
+                instructions.push(Instruction::SPrint(x_reg))
+            }
+            [name, ref operands @ ..] if extensions.arity(name) == Some(operands.len()) => {
+                let operands = operands
+                    .iter()
+                    .map(|operand| parse_token(operand))
+                    .collect::<Result<_, _>>()?;
+                instructions.push(Instruction::Custom(name.to_string(), operands))
+            }
             [_, ..] => {
                 return Result::Err(ParseError::InstructionNotFoundOrWrongArgs(format!(
                     "Not found instruction or wrong args on line {i}, error: {line}"
@@ -163,6 +798,64 @@ pub fn parse_instructions(input: Vec<&str>) -> Result<Vec<Instruction>, ParseErr
     Ok(instructions)
 }
 
+/// Instructions together with what tooling needs to know about where they came from.
+/// Derefs to the instructions, so a program can be passed wherever `&[Instruction]` is
+/// expected. Clones share the instructions, so one program can run on any number of VMs,
+/// e.g. one per thread.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub instructions: Arc<[Instruction]>,
+    /// File the program was read from, if any.
+    pub file: Option<String>,
+    /// Index of the first instruction to execute.
+    pub entry: usize,
+}
+
+impl Program {
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        Program {
+            instructions: instructions.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    pub fn with_entry(mut self, entry: usize) -> Self {
+        self.entry = entry;
+        self
+    }
+}
+
+impl From<Vec<Instruction>> for Program {
+    fn from(instructions: Vec<Instruction>) -> Self {
+        Program::new(instructions)
+    }
+}
+
+impl std::ops::Deref for Program {
+    type Target = [Instruction];
+    fn deref(&self) -> &Self::Target {
+        &self.instructions
+    }
+}
+
+/// Parses `input` into a [`Program`] starting at its first instruction.
+pub fn parse_program(input: Vec<&str>) -> Result<Program, ParseError> {
+    parse_instructions(input).map(Program::new)
+}
+
+/// Parses `input` into a [`Program`], also accepting the instructions of `extensions`.
+pub fn parse_program_with(
+    input: Vec<&str>,
+    extensions: &Extensions,
+) -> Result<Program, ParseError> {
+    parse_instructions_with(input, extensions).map(Program::new)
+}
+
 // ----- parser tests
 
 #[cfg(test)]
@@ -193,6 +886,213 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_io_instructions() {
+        let a = Register::of("a".to_string());
+        assert_eq!(
+            parse_instructions(vec!["in a 1", "out 0 a", "poll a 0"]).unwrap(),
+            vec![
+                In(a.clone(), Constant::of(1)),
+                Out(Constant::of(0), a.clone()),
+                Poll(a, Constant::of(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_string_instructions() {
+        let s = Register::of("s".to_string());
+        let n = Register::of("n".to_string());
+        assert_eq!(
+            parse_instructions(vec![
+                r#"smov s "say \"hi\"\n""#,
+                "scat s s",
+                "slen n s",
+                "sprint s"
+            ])
+            .unwrap(),
+            vec![
+                SMov(s.clone(), "say \"hi\"\n".to_string()),
+                SCat(s.clone(), s.clone()),
+                SLen(n, s.clone()),
+                SPrint(s.clone()),
+            ]
+        );
+        assert_eq!(
+            parse_instructions(vec!["  smov\ts\t\"a  b\" "]).unwrap(),
+            vec![SMov(s, "a  b".to_string())]
+        );
+        assert!(parse_instructions(vec!["smov s hi"]).is_err());
+        assert!(parse_instructions(vec![r#"smov s "a"b""#]).is_err());
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        let fx = |v: f64| Constant::of((v * 65536.0) as i32);
+        assert_eq!(fx(1.5).fx_mul(fx(-2.25)), fx(-3.375));
+        assert_eq!(fx(1.0).fx_div(fx(4.0)), Some(fx(0.25)));
+        assert_eq!(fx(-3.0).fx_div(fx(0.5)), Some(fx(-6.0)));
+        assert_eq!(fx(1.0).fx_div(Constant::ZERO), None);
+    }
+
+    #[test]
+    fn test_bit_fields() {
+        let field = |offset, len| BitField::new(Constant::of(offset), Constant::of(len)).unwrap();
+        let word = Constant::of(0x1234_5678);
+        assert_eq!(word.bit_extract(field(4, 8)), Constant::of(0x67));
+        assert_eq!(
+            Constant::of(-1).bit_extract(field(28, 4)),
+            Constant::of(0xf)
+        );
+        assert_eq!(word.bit_extract(field(0, 32)), word);
+        assert_eq!(
+            word.bit_insert(Constant::of(0xabc), field(8, 8)),
+            Constant::of(0x1234_bc78)
+        );
+        assert_eq!(
+            Constant::ZERO.bit_insert(Constant::of(1), field(31, 1)),
+            Constant::of(i32::MIN)
+        );
+        assert!(parse_instructions(vec!["bext a b 30 4"]).is_err());
+        assert!(parse_instructions(vec!["bext a b 0 0"]).is_err());
+        assert!(parse_instructions(vec!["bins a b -1 2"]).is_err());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let word = Constant::of(0x8000_0001_u32 as i32);
+        assert_eq!(word.rotate_left(Constant::of(1)), Constant::of(3));
+        assert_eq!(
+            word.rotate_left(Constant::of(-1)),
+            Constant::of(0xc000_0000_u32 as i32)
+        );
+        assert_eq!(word.rotate_left(Constant::of(32)), word);
+        assert_eq!(
+            word.rotate_right(Constant::of(1)),
+            Constant::of(0xc000_0000_u32 as i32)
+        );
+        assert_eq!(word.rotate_right(Constant::of(-1)), Constant::of(3));
+    }
+
+    #[test]
+    fn test_division() {
+        let (seven, two) = (Constant::of(7), Constant::of(2));
+        assert_eq!(seven.quotient(two), Some(Constant::of(3)));
+        assert_eq!(Constant::of(-7).quotient(two), Some(Constant::of(-3)));
+        assert_eq!(Constant::of(-7).remainder(two), Some(Constant::of(-1)));
+        assert_eq!(seven.remainder(Constant::of(-2)), Some(Constant::of(1)));
+        assert_eq!(
+            Constant::of(i32::MIN).quotient(Constant::of(-1)),
+            Some(Constant::of(i32::MIN))
+        );
+        assert_eq!(seven.quotient(Constant::ZERO), None);
+        assert_eq!(seven.remainder(Constant::ZERO), None);
+    }
+
+    #[test]
+    fn test_shift() {
+        let word = Constant::of(-8);
+        assert_eq!(word.shift_left(Constant::of(4)), Constant::of(-128));
+        assert_eq!(word.shift_right(Constant::of(1)), Constant::of(0x7fff_fffc));
+        assert_eq!(word.shift_right(Constant::of(31)), Constant::of(1));
+        assert_eq!(word.shift_left(Constant::of(32)), Constant::ZERO);
+        assert_eq!(word.shift_right(Constant::of(-1)), Constant::ZERO);
+        assert_eq!(word.shift_left(Constant::ZERO), word);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let holding = |x, y| {
+            let flags = Flags::compare(Constant::of(x), Constant::of(y));
+            Condition::ALL
+                .into_iter()
+                .filter(|condition| condition.holds(flags))
+                .map(Condition::opcode)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(holding(3, 3), ["je", "jge", "jle"]);
+        assert_eq!(holding(i32::MIN, 1), ["jne", "jl", "jle"]);
+        assert_eq!(holding(i32::MAX, -1), ["jne", "jg", "jge"]);
+        // no cmp yet
+        assert!(Condition::GreaterOrEqual.holds(Flags::default()));
+    }
+
+    #[test]
+    fn test_carry_arithmetic() {
+        let max = Constant::of(-1);
+        let one = Constant::of(1);
+        assert_eq!(max.add_with_carry(one, false), (Constant::ZERO, true));
+        assert_eq!(
+            max.add_with_carry(Constant::ZERO, true),
+            (Constant::ZERO, true)
+        );
+        assert_eq!(one.add_with_carry(one, true), (Constant::of(3), false));
+        assert_eq!(Constant::ZERO.sub_with_borrow(one, false), (max, true));
+        assert_eq!(
+            one.sub_with_borrow(Constant::ZERO, true),
+            (Constant::ZERO, false)
+        );
+        assert_eq!(max.mul_high(max), Constant::of(-2));
+        assert_eq!(
+            Constant::of(0x10000).mul_high(Constant::of(0x30000)),
+            Constant::of(3)
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let input = vec![
+            "mov a -1",
+            "add a b",
+            "sub a b",
+            "mul a b",
+            "div a b",
+            "mod a b",
+            "jnz a b",
+            "jz a -2",
+            "jmp 3",
+            "jmp b",
+            "cmp a 3",
+            "je -1",
+            "jne b",
+            "jg 2",
+            "jl 0",
+            "jge -3",
+            "jle c",
+            "print a",
+            "read a",
+            "out 0 a",
+            r#"smov s "a \"b\" \\ c\n""#,
+            "slen a s",
+            "fxmul a b",
+            "fxdiv a b",
+            "bext a b 4 8",
+            "bins a b 0 32",
+            "and a 255",
+            "or a b",
+            "xor a -1",
+            "not a",
+            "shl a 4",
+            "shr a b",
+            "rol a 3",
+            "ror a b",
+            "popcnt a b",
+            "clz a b",
+            "ctz a b",
+            "mulh a b",
+            "addc a b",
+            "subb a b",
+            "emit loop.count a",
+            "syscall 3",
+        ];
+        let instructions = parse_instructions(input.clone()).unwrap();
+        let printed = instructions
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(printed, input);
+    }
+
     #[test]
     fn test_unknown_instruction() {
         let instructions = parse_instructions(vec!["mov a 1", "mbx a 2"]);
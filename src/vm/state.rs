@@ -0,0 +1,66 @@
+use std::fmt::Display;
+
+use super::parser::{Flags, Instruction};
+use super::Vm;
+
+/// Registers, flags and pc of a VM at one point of a run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct State {
+    /// `None` once the program has ended.
+    pub pc: Option<usize>,
+    /// Registers sorted by name.
+    pub registers: Vec<(String, i32)>,
+    /// String registers sorted by name.
+    pub strings: Vec<(String, String)>,
+    pub carry: bool,
+    pub flags: Flags,
+}
+
+impl State {
+    pub(crate) fn of(vm: &Vm, instructions: &[Instruction]) -> Self {
+        let mut registers = vm
+            .registers
+            .iter()
+            .map(|(reg, value)| (reg.to_string(), **value))
+            .collect::<Vec<_>>();
+        registers.sort();
+        let mut strings = vm
+            .strings
+            .iter()
+            .map(|(reg, text)| (reg.to_string(), text.clone()))
+            .collect::<Vec<_>>();
+        strings.sort();
+        State {
+            pc: Some(vm.pc).filter(|pc| *pc < instructions.len()),
+            registers,
+            strings,
+            carry: vm.carry,
+            flags: vm.flags,
+        }
+    }
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pc {
+            Some(pc) => write!(f, "line {}", pc + 1)?,
+            None => write!(f, "halted")?,
+        }
+        for (reg, value) in &self.registers {
+            write!(f, ", {reg} = {value}")?;
+        }
+        for (reg, text) in &self.strings {
+            write!(f, ", {reg} = {text:?}")?;
+        }
+        if self.carry {
+            write!(f, ", carry")?;
+        }
+        if self.flags.zero {
+            write!(f, ", zero")?;
+        }
+        if self.flags.negative {
+            write!(f, ", negative")?;
+        }
+        Ok(())
+    }
+}
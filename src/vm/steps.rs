@@ -0,0 +1,107 @@
+use super::analysis::writes;
+use super::error::VmError;
+use super::parser::{Constant, Instruction, Program, Register};
+use super::{StepOutcome, Vm};
+
+/// One executed instruction, yielded by [`Vm::run_iter`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub pc: usize,
+    pub instruction: Instruction,
+    /// Register written by the instruction and its new value.
+    pub write: Option<(Register, Constant)>,
+}
+
+/// Iterator over the steps of a run, ends once the program ends, runs out of budget or
+/// after the first error.
+pub struct Steps<'a> {
+    vm: &'a mut Vm,
+    program: &'a Program,
+    done: bool,
+}
+
+impl Iterator for Steps<'_> {
+    type Item = Result<Step, VmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let pc = self.vm.pc;
+        match self.vm.step(self.program) {
+            Ok(StepOutcome::Continued) => {
+                let instruction = self.program[pc].clone();
+                let write = writes(&instruction).and_then(|x| Some((x.clone(), self.vm.get(x)?)));
+                Some(Ok(Step {
+                    pc,
+                    instruction,
+                    write,
+                }))
+            }
+            Ok(StepOutcome::Halted | StepOutcome::BudgetExhausted) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl Vm {
+    /// Runs `program` from its entry point one step per call to `next`.
+    pub fn run_iter<'a>(&'a mut self, program: &'a Program) -> Steps<'a> {
+        self.start_program(program, program.entry);
+        Steps {
+            vm: self,
+            program,
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::error::ErrorKind;
+    use crate::vm::parser::parse_program;
+
+    #[test]
+    fn test_run_iter() {
+        let program = parse_program(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "print b",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        let writes = vm
+            .run_iter(&program)
+            .take(4)
+            .map(|step| {
+                step.unwrap()
+                    .write
+                    .map(|(x, value)| (x.to_string(), *value))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            writes,
+            [Some(("a", 2)), Some(("b", -1)), Some(("a", 1)), None]
+                .map(|write| write.map(|(x, value)| (x.to_string(), value)))
+        );
+
+        let mut vm = Vm::new();
+        let jump = vm.run_iter(&program).find(
+            |step| matches!(step, Ok(step) if step.pc == 3 && step.instruction.opcode() == "jnz"),
+        );
+        assert!(jump.is_some());
+
+        let mut vm = Vm::new();
+        let steps = vm.run_iter(&program).collect::<Vec<_>>();
+        assert_eq!(steps.len(), 7);
+        assert!(matches!(
+            steps.last(),
+            Some(Err(err)) if matches!(err.kind, ErrorKind::Unprintable { line: 5, .. })
+        ));
+    }
+}
@@ -0,0 +1,185 @@
+use std::{fmt::Display, str::FromStr, sync::Arc};
+
+use super::error::VmError;
+use super::parser::{
+    parse_instructions, ConstOrReg, Constant, Flags, Instruction, Program, Register,
+};
+use super::{ExitStatus, Vm};
+
+// A run can be cut into slices: give the VM a budget, suspend it once the budget is used up
+// and resume it later, possibly in another process. The text form reuses the assembly
+// syntax for the registers:
+//
+//   pc 3
+//   carry
+//   zero
+//   mov a 5
+//   smov s "hi"
+
+/// Registers and pc of a VM between two slices of a run, see [`Vm::suspend`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuspendedVm {
+    pub pc: usize,
+    /// Registers sorted by name.
+    pub registers: Vec<(Register, Constant)>,
+    /// String registers sorted by name.
+    pub strings: Vec<(Register, String)>,
+    pub carry: bool,
+    pub flags: Flags,
+}
+
+impl Vm {
+    /// Captures what [`Vm::resume`] needs to continue the run where it stopped. Devices,
+    /// handlers and the budget stay with the VM.
+    pub fn suspend(&self) -> SuspendedVm {
+        let mut registers = self
+            .registers
+            .iter()
+            .map(|(reg, value)| (reg.clone(), *value))
+            .collect::<Vec<_>>();
+        registers.sort_by_key(|(reg, _)| reg.to_string());
+        let mut strings = self
+            .strings
+            .iter()
+            .map(|(reg, text)| (reg.clone(), text.clone()))
+            .collect::<Vec<_>>();
+        strings.sort_by_key(|(reg, _)| reg.to_string());
+        SuspendedVm {
+            pc: self.pc,
+            registers,
+            strings,
+            carry: self.carry,
+            flags: self.flags,
+        }
+    }
+
+    /// Replaces the registers with the suspended ones and runs `program` from the suspended
+    /// pc, like [`Vm::interpret`].
+    pub fn resume(
+        &mut self,
+        suspended: &SuspendedVm,
+        program: &Program,
+    ) -> Result<ExitStatus, VmError> {
+        self.registers = Arc::new(suspended.registers.iter().cloned().collect());
+        self.strings = Arc::new(suspended.strings.iter().cloned().collect());
+        self.carry = suspended.carry;
+        self.flags = suspended.flags;
+        self.start_program(program, suspended.pc);
+        self.run(program)
+    }
+}
+
+impl Display for SuspendedVm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pc {}", self.pc)?;
+        if self.carry {
+            write!(f, "\ncarry")?;
+        }
+        if self.flags.zero {
+            write!(f, "\nzero")?;
+        }
+        if self.flags.negative {
+            write!(f, "\nnegative")?;
+        }
+        for (reg, value) in &self.registers {
+            let mov = Instruction::Mov(reg.clone(), ConstOrReg::Const(*value));
+            write!(f, "\n{mov}")?;
+        }
+        for (reg, text) in &self.strings {
+            write!(f, "\n{}", Instruction::SMov(reg.clone(), text.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SuspendedVm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        let pc = lines
+            .next()
+            .and_then(|line| line.strip_prefix("pc "))
+            .and_then(|pc| pc.parse().ok())
+            .ok_or("expected pc <n> on the first line")?;
+        let mut lines = lines.peekable();
+        let carry = lines.next_if_eq(&"carry").is_some();
+        let flags = Flags {
+            zero: lines.next_if_eq(&"zero").is_some(),
+            negative: lines.next_if_eq(&"negative").is_some(),
+        };
+        let mut suspended = SuspendedVm {
+            pc,
+            registers: Vec::new(),
+            strings: Vec::new(),
+            carry,
+            flags,
+        };
+        let lines = lines.collect::<Vec<_>>();
+        if lines.is_empty() {
+            return Ok(suspended);
+        }
+        for instruction in parse_instructions(lines).map_err(|err| format!("{err:?}"))? {
+            match instruction {
+                Instruction::Mov(reg, ConstOrReg::Const(value)) => {
+                    suspended.registers.push((reg, value))
+                }
+                Instruction::SMov(reg, text) => suspended.strings.push((reg, text)),
+                other => return Err(format!("expected a register value, found {other}")),
+            }
+        }
+        Ok(suspended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_program;
+
+    #[test]
+    fn test_run_in_slices() {
+        // Sums 10 + 9 + ... + 1 into b.
+        let program = parse_program(vec![
+            "mov a 10",
+            "mov b 0",
+            "mov c -1",
+            "smov s \"sum \\\"b\\\"\"",
+            "add b a",
+            "add a c",
+            "jnz a -2",
+        ])
+        .unwrap();
+        let mut vm = Vm::builder().budget(7).build();
+        let mut status = vm.interpret(&program).unwrap();
+        let mut slices = 1;
+        while status == ExitStatus::BudgetExhausted {
+            let text = vm.suspend().to_string();
+            let suspended = text.parse::<SuspendedVm>().unwrap();
+            assert_eq!(suspended, vm.suspend());
+            vm = Vm::new();
+            vm.set_budget(7);
+            status = vm.resume(&suspended, &program).unwrap();
+            slices += 1;
+        }
+        assert_eq!(status, ExitStatus::Completed);
+        assert_eq!(slices, 5);
+        assert_eq!(vm.get(&"b".parse().unwrap()), Some(Constant::of(55)));
+        assert_eq!(
+            vm.suspend().to_string(),
+            "pc 7\ncarry\nmov a 0\nmov b 55\nmov c -1\nsmov s \"sum \\\"b\\\"\""
+        );
+        let suspended = "pc 2\nnegative\nmov a 1".parse::<SuspendedVm>().unwrap();
+        assert_eq!(
+            suspended.flags,
+            Flags {
+                zero: false,
+                negative: true
+            }
+        );
+        assert_eq!(suspended.to_string(), "pc 2\nnegative\nmov a 1");
+        assert_eq!(
+            "pc 1\nprint a".parse::<SuspendedVm>(),
+            Err("expected a register value, found print a".to_string())
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use super::error::ErrorKind;
+use super::parser::parse_program;
+use super::Vm;
+
+// Helpers for tests that run a whole program and check what it printed and left behind:
+//
+//   assert_program!("mov a 104\nprint a", output = "h", regs = { a: 104 });
+
+/// How a program run by [`run_capture`] ended.
+#[derive(Debug, PartialEq)]
+pub enum VmExit {
+    Halted,
+    Trapped(ErrorKind),
+}
+
+/// Integer registers by name.
+pub type Registers = BTreeMap<String, i32>;
+
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the program in `src` on a VM without devices, returning how it ended, what it
+/// printed and its registers.
+pub fn run_capture(src: &str) -> (VmExit, String, Registers) {
+    let instructions = parse_program(src.lines().map(str::trim).collect()).unwrap();
+    let capture = Capture::default();
+    let mut vm = Vm::new();
+    vm.set_output(Box::new(capture.clone()));
+    let exit = match vm.interpret(&instructions) {
+        Ok(_) => VmExit::Halted,
+        Err(err) => VmExit::Trapped(err.kind),
+    };
+    let registers = vm
+        .registers
+        .iter()
+        .map(|(reg, value)| (reg.to_string(), **value))
+        .collect();
+    let output = String::from_utf8_lossy(&capture.0.lock().unwrap()).into_owned();
+    (exit, output, registers)
+}
+
+/// Runs a program with [`run_capture`], asserting that it halts and optionally what it
+/// printed and the values of some registers.
+#[macro_export]
+macro_rules! assert_program {
+    ($src:expr $(, output = $output:expr)? $(, regs = { $($reg:ident: $value:expr),* $(,)? })? $(,)?) => {{
+        let (exit, _output, _registers) = $crate::vm::testing::run_capture($src);
+        assert_eq!(exit, $crate::vm::testing::VmExit::Halted);
+        $(assert_eq!(_output, $output);)?
+        $($(
+            assert_eq!(
+                _registers.get(stringify!($reg)).copied(),
+                Some($value),
+                "register {}",
+                stringify!($reg)
+            );
+        )*)?
+    }};
+}
+
+pub use assert_program;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::Register;
+
+    #[test]
+    fn test_run_capture() {
+        let (exit, output, registers) = run_capture("mov a 65\nprint a\nmov b 0\nprint b");
+        assert_eq!(output, "A\0");
+        assert_eq!(
+            registers,
+            Registers::from([("a".to_string(), 65), ("b".to_string(), 0)])
+        );
+        assert!(matches!(exit, VmExit::Halted));
+        let (exit, _, _) = run_capture("mov a 1\nfxdiv a b");
+        assert_eq!(
+            exit,
+            VmExit::Trapped(ErrorKind::Uninitialized {
+                register: Register::of("b".to_string()),
+                line: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, fmt::Display, ops::Range, str::FromStr};
+
+use super::analysis::cfg::Cfg;
+use super::parser::Instruction;
+
+// Simulated cost of a run: every executed instruction costs a fixed number of cycles for
+// its opcode, so results only depend on the program and never on the host.
+
+/// Cycles per opcode, overriding the defaults where given.
+#[derive(Debug, Default, PartialEq)]
+pub struct Latencies {
+    overrides: HashMap<&'static str, u64>,
+}
+
+impl Latencies {
+    fn default_cycles(opcode: &str) -> u64 {
+        match opcode {
+            "mul" | "mulh" | "fxmul" => 3,
+            "scat" => 4,
+            "div" | "mod" | "fxdiv" => 20,
+            "print" | "read" | "sprint" | "syscall" | "in" | "out" | "poll" => 10,
+            _ => 1,
+        }
+    }
+
+    pub fn cycles(&self, instruction: &Instruction) -> u64 {
+        let opcode = instruction.opcode();
+        self.overrides
+            .get(opcode)
+            .copied()
+            .unwrap_or_else(|| Self::default_cycles(opcode))
+    }
+}
+
+impl FromStr for Latencies {
+    type Err = String;
+    /// Comma separated `opcode=cycles` pairs, e.g. `fxdiv=40,in=100`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overrides = HashMap::new();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (name, cycles) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected opcode=cycles, found {pair}"))?;
+            let opcode = Instruction::OPCODES
+                .into_iter()
+                .find(|opcode| *opcode == name)
+                .ok_or_else(|| format!("unknown opcode {name}"))?;
+            let cycles = cycles
+                .parse()
+                .map_err(|_| format!("expected a cycle count for {name}, found {cycles}"))?;
+            overrides.insert(opcode, cycles);
+        }
+        Ok(Latencies { overrides })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BlockCycles {
+    pub block: usize,
+    pub pcs: Range<usize>,
+    pub cycles: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Timing {
+    pub total: u64,
+    /// Every basic block, in program order.
+    pub blocks: Vec<BlockCycles>,
+}
+
+/// Simulated cycles of a run from its per pc hit counts, see [`super::Vm::enable_coverage`].
+pub fn timing(instructions: &[Instruction], hits: &[u64], latencies: &Latencies) -> Timing {
+    let cycles = |pc: usize| hits.get(pc).unwrap_or(&0) * latencies.cycles(&instructions[pc]);
+    let blocks = Cfg::build(instructions)
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| BlockCycles {
+            block: i,
+            pcs: block.start..block.end,
+            cycles: (block.start..block.end).map(cycles).sum(),
+        })
+        .collect::<Vec<_>>();
+    Timing {
+        total: blocks.iter().map(|block| block.cycles).sum(),
+        blocks,
+    }
+}
+
+impl Display for Timing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "simulated cycles: {}", self.total)?;
+        for block in &self.blocks {
+            let percent = if self.total == 0 {
+                0.0
+            } else {
+                block.cycles as f64 * 100.0 / self.total as f64
+            };
+            write!(
+                f,
+                "\nblock {} (lines {}-{}): {} cycles ({percent:.1}%)",
+                block.block,
+                block.pcs.start + 1,
+                block.pcs.end,
+                block.cycles
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::parse_program;
+    use crate::vm::Vm;
+
+    #[test]
+    fn test_timing() {
+        let instructions = parse_program(vec![
+            "mov a 2",
+            "mov b -1",
+            "add a b",
+            "jnz a -1",
+            "fxdiv b b",
+        ])
+        .unwrap();
+        let mut vm = Vm::new();
+        vm.enable_coverage();
+        vm.interpret(&instructions).unwrap();
+        let hits = vm.coverage().unwrap();
+        assert_eq!(
+            timing(&instructions, hits, &Latencies::default()).to_string(),
+            "simulated cycles: 26\n\
+             block 0 (lines 1-2): 2 cycles (7.7%)\n\
+             block 1 (lines 3-4): 4 cycles (15.4%)\n\
+             block 2 (lines 5-5): 20 cycles (76.9%)"
+        );
+        let latencies = "add=5,fxdiv=1".parse::<Latencies>().unwrap();
+        assert_eq!(timing(&instructions, hits, &latencies).total, 15);
+        assert_eq!(
+            "fly=1".parse::<Latencies>(),
+            Err("unknown opcode fly".to_string())
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    time::Instant,
+};
+
+use super::parser::{Constant, Instruction, Register};
+
+/// Observer of every executed instruction, installed with `Vm::set_tracer`. An error traps
+/// the program with an output error.
+pub trait Tracer: Send {
+    fn before(&mut self, _pc: usize, _instruction: &Instruction) -> io::Result<()> {
+        Ok(())
+    }
+    /// Called once the instruction at `pc` has run, with the updated registers.
+    fn after(
+        &mut self,
+        _pc: usize,
+        _instruction: &Instruction,
+        _registers: &HashMap<Register, Constant>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+    /// Completes the trace once the program has stopped.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Escapes `s` to be written between the quotes of a JSON string.
+pub fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Granularity {
+    Instruction,
+    /// One event per run of instructions ending with a jump.
+    Block,
+}
+
+/// Writes a Chrome `about:tracing` / Perfetto JSON trace, the array is closed by
+/// [`Tracer::finish`] or when dropped.
+pub struct ChromeTracer<W: Write> {
+    out: W,
+    granularity: Granularity,
+    origin: Instant,
+    first: bool,
+    closed: bool,
+    started: f64,
+    block_start: Option<(usize, f64)>,
+}
+
+impl<W: Write> ChromeTracer<W> {
+    pub fn new(mut out: W, granularity: Granularity) -> io::Result<Self> {
+        out.write_all(b"[")?;
+        Ok(ChromeTracer {
+            out,
+            granularity,
+            origin: Instant::now(),
+            first: true,
+            closed: false,
+            started: 0.0,
+            block_start: None,
+        })
+    }
+
+    fn now(&self) -> f64 {
+        self.origin.elapsed().as_secs_f64() * 1_000_000.0
+    }
+
+    fn event(
+        &mut self,
+        name: &str,
+        category: &str,
+        pc: usize,
+        ts: f64,
+        dur: f64,
+    ) -> io::Result<()> {
+        let separator = if self.first { "\n" } else { ",\n" };
+        self.first = false;
+        write!(
+            self.out,
+            "{separator}{{\"name\":\"{}\",\"cat\":\"{category}\",\"ph\":\"X\",\"ts\":{ts:.3},\"dur\":{dur:.3},\"pid\":1,\"tid\":1,\"args\":{{\"pc\":{pc}}}}}",
+            escape_json(name)
+        )
+    }
+
+    fn end_block(&mut self, end_pc: usize) -> io::Result<()> {
+        match self.block_start.take() {
+            Some((start, ts)) => {
+                let dur = self.now() - ts;
+                let name = format!("pc {start}..{end_pc}");
+                self.event(&name, "block", start, ts, dur)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        if let Some((start, _)) = self.block_start {
+            // the program ran off its end, the last block has no jump
+            self.end_block(start + 1)?;
+        }
+        self.out.write_all(b"\n]\n")?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write + Send> Tracer for ChromeTracer<W> {
+    fn before(&mut self, pc: usize, _instruction: &Instruction) -> io::Result<()> {
+        let now = self.now();
+        self.started = now;
+        if self.granularity == Granularity::Block && self.block_start.is_none() {
+            self.block_start = Some((pc, now));
+        }
+        Ok(())
+    }
+
+    fn after(
+        &mut self,
+        pc: usize,
+        instruction: &Instruction,
+        _registers: &HashMap<Register, Constant>,
+    ) -> io::Result<()> {
+        match self.granularity {
+            Granularity::Instruction => {
+                let dur = self.now() - self.started;
+                self.event(
+                    &instruction.to_string(),
+                    "instruction",
+                    pc,
+                    self.started,
+                    dur,
+                )
+            }
+            Granularity::Block if instruction.jump_offset().is_some() => self.end_block(pc + 1),
+            Granularity::Block => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close()
+    }
+}
+
+impl<W: Write> Drop for ChromeTracer<W> {
+    fn drop(&mut self) {
+        self.close().ok();
+    }
+}
+
+/// Writes one JSON object per executed instruction with the registers it changed:
+/// `{"step":0,"pc":0,"instruction":"mov a 1","changes":{"a":1}}`.
+pub struct JsonlTracer<W: Write> {
+    out: W,
+    step: u64,
+    previous: HashMap<Register, Constant>,
+}
+
+impl<W: Write> JsonlTracer<W> {
+    pub fn new(out: W) -> Self {
+        JsonlTracer {
+            out,
+            step: 0,
+            previous: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Write + Send> Tracer for JsonlTracer<W> {
+    fn after(
+        &mut self,
+        pc: usize,
+        instruction: &Instruction,
+        registers: &HashMap<Register, Constant>,
+    ) -> io::Result<()> {
+        let mut changes = registers
+            .iter()
+            .filter(|(reg, value)| self.previous.get(*reg) != Some(*value))
+            .map(|(reg, value)| (reg.to_string(), *value))
+            .collect::<Vec<_>>();
+        changes.sort();
+        let fields = changes
+            .iter()
+            .map(|(reg, value)| format!("\"{}\":{value}", escape_json(reg)))
+            .collect::<Vec<_>>();
+        writeln!(
+            self.out,
+            "{{\"step\":{},\"pc\":{pc},\"instruction\":\"{}\",\"changes\":{{{}}}}}",
+            self.step,
+            escape_json(&instruction.to_string()),
+            fields.join(",")
+        )?;
+        for (reg, value) in changes {
+            self.previous.insert(Register::of(reg), value);
+        }
+        self.step += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Drop for JsonlTracer<W> {
+    fn drop(&mut self) {
+        self.out.flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::error::ErrorKind;
+    use crate::vm::parser::parse_program;
+    use crate::vm::Vm;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn trace(granularity: Granularity) -> String {
+        let instructions = parse_program(vec![
+            "mov a 2", "mov b -1", "add a b", "jnz a -1", "mov c 1",
+        ])
+        .unwrap();
+        let buffer = SharedBuffer::default();
+        let mut vm = Vm::new();
+        vm.set_tracer(Box::new(
+            ChromeTracer::new(buffer.clone(), granularity).unwrap(),
+        ));
+        vm.interpret(&instructions).unwrap();
+        vm.finish_trace().unwrap();
+        // dropping a finished tracer must not close the array twice
+        drop(vm);
+        buffer.contents()
+    }
+
+    fn names(trace: &str) -> Vec<&str> {
+        trace
+            .split("\"name\":\"")
+            .skip(1)
+            .map(|event| &event[..event.find('"').unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn test_instruction_events() {
+        let trace = trace(Granularity::Instruction);
+        assert!(trace.starts_with("[\n{"));
+        assert!(trace.ends_with("}\n]\n"));
+        assert_eq!(
+            names(&trace),
+            vec!["mov a 2", "mov b -1", "add a b", "jnz a -1", "add a b", "jnz a -1", "mov c 1"]
+        );
+        assert!(trace.contains("\"ph\":\"X\""));
+        assert!(trace.contains("\"args\":{\"pc\":4}"));
+    }
+
+    #[test]
+    fn test_block_events() {
+        let trace = trace(Granularity::Block);
+        assert_eq!(names(&trace), vec!["pc 0..4", "pc 2..4", "pc 4..5"]);
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let instructions = parse_program(vec!["mov a 1", "mov b a", "add a b", "mov b 1"]).unwrap();
+        let buffer = SharedBuffer::default();
+        let mut vm = Vm::new();
+        vm.set_tracer(Box::new(JsonlTracer::new(io::BufWriter::new(
+            buffer.clone(),
+        ))));
+        vm.interpret(&instructions).unwrap();
+        vm.finish_trace().unwrap();
+        assert_eq!(
+            buffer.contents(),
+            [
+                r#"{"step":0,"pc":0,"instruction":"mov a 1","changes":{"a":1}}"#,
+                r#"{"step":1,"pc":1,"instruction":"mov b a","changes":{"b":1}}"#,
+                r#"{"step":2,"pc":2,"instruction":"add a b","changes":{"a":2}}"#,
+                r#"{"step":3,"pc":3,"instruction":"mov b 1","changes":{}}"#,
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_write_failure_traps() {
+        let instructions = parse_program(vec!["mov a 1"]).unwrap();
+        let mut vm = Vm::new();
+        vm.set_tracer(Box::new(JsonlTracer::new(io::Cursor::new([0; 0]))));
+        let err = vm.interpret(&instructions).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Output { line: 1, .. }));
+    }
+
+    #[test]
+    fn test_escape_json() {
+        assert_eq!(escape_json("a\"b\\c\n"), "a\\\"b\\\\c\\u000a");
+    }
+}